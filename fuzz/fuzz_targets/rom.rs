@@ -0,0 +1,21 @@
+#![no_main]
+
+//! Arbitrary bytes as a ROM, run for a bounded number of instructions.
+
+use chip8::cpu::{QuirkPreset, Quirks, CPU};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_CYCLES: usize = 10_000;
+
+fuzz_target!(|rom: &[u8]| {
+    for preset in [QuirkPreset::Chip8, QuirkPreset::Schip, QuirkPreset::XoChip] {
+        let mut cpu = CPU::with_quirks(Quirks::preset(preset));
+        cpu.load_rom(rom);
+        for _ in 0..MAX_CYCLES {
+            // Errors are fine, panics are not
+            if cpu.cycle().is_err() {
+                break;
+            }
+        }
+    }
+});
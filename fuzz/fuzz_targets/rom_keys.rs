@@ -0,0 +1,54 @@
+#![no_main]
+
+//! Arbitrary ROM, quirks and timing, with key presses between frames.
+
+use chip8::{
+    cpu::{Quirks, CPU},
+    machine::Machine,
+    timing::Timing,
+};
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+const MAX_FRAMES: usize = 600;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    rom: Vec<u8>,
+    quirks: [bool; 8],
+    vip_timing: bool,
+    instructions_per_frame: u8,
+    // Key (low nibble) and whether it goes down or up, before each frame
+    keys: Vec<(u8, bool)>,
+}
+
+fuzz_target!(|input: Input| {
+    let [shift_uses_vy, jump_uses_vx, key_wait_on_press, display_wait, clip_sprites, index_overflow_sets_vf, logic_resets_vf, load_store_increments_index] =
+        input.quirks;
+    let mut cpu = CPU::with_quirks(Quirks {
+        shift_uses_vy,
+        jump_uses_vx,
+        key_wait_on_press,
+        display_wait,
+        clip_sprites,
+        index_overflow_sets_vf,
+        logic_resets_vf,
+        load_store_increments_index,
+    });
+    cpu.load_rom(&input.rom);
+
+    let mut machine = Machine::new(cpu, input.instructions_per_frame as u32);
+    if input.vip_timing {
+        machine.set_timing(Timing::Vip);
+    }
+
+    let mut keys = input.keys.iter();
+    for _ in 0..MAX_FRAMES {
+        if let Some(&(key, pressed)) = keys.next() {
+            machine.cpu_mut().keypress((key & 0xF) as usize, pressed);
+        }
+        if machine.run_frame().is_err() {
+            break;
+        }
+    }
+});
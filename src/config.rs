@@ -0,0 +1,361 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use chip8::cpu::{QuirkPreset, Quirks};
+use chip8::machine::DEFAULT_INSTRUCTIONS_PER_FRAME;
+use chip8::timing::Timing;
+use crate::frontend::{
+    crt::Effect,
+    display::Scaling,
+    gamepad::{ButtonMap, GamepadProfile},
+    input::Chip8KeyCode,
+    keymap::{Keymap, KeymapPreset},
+    palette::{Palette, Rgb},
+};
+
+/// Loaded from the working directory when no config file is given on the command line.
+pub const DEFAULT_CONFIG: &str = "chip8.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub display: DisplayConfig,
+    pub machine: MachineConfig,
+    pub keymap: KeymapConfig,
+    pub gamepad: GamepadConfig,
+    pub quirks: QuirksConfig,
+    /// Per-ROM settings, keyed by the ROM's file name. These are applied last, so they
+    /// win over both the rest of the file and the command line.
+    pub roms: HashMap<String, RomConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomConfig {
+    pub keymap: KeymapConfig,
+    pub gamepad: GamepadConfig,
+    pub quirks: QuirksConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MachineConfig {
+    /// ipf, or vip for the cycle timings of the COSMAC VIP
+    pub timing: Timing,
+    /// Instructions executed per 60 Hz frame
+    pub instructions_per_frame: u32,
+    /// Multiplier for `instructions_per_frame`, from 0.25 to 16
+    pub speed: f32,
+    /// Run as fast as possible, for benchmarking
+    pub turbo: bool,
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            timing: Timing::default(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            speed: 1.0,
+            turbo: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    /// Named palette, see `Palette::NAMES`
+    pub palette: Option<Palette>,
+    /// Custom four-colour palette, takes precedence over `palette`
+    pub colors: Option<[Rgb; 4]>,
+    pub foreground: Option<Rgb>,
+    pub background: Option<Rgb>,
+    /// Phosphor decay every 1/60 s between 0 and 0.95, 0 disables the afterglow
+    pub persistence: f32,
+    /// Post-processing effect, see `Effect::NAMES`
+    pub effect: Effect,
+    /// integer or fractional
+    pub scaling: Scaling,
+    pub fullscreen: bool,
+    pub vsync: bool,
+}
+
+impl DisplayConfig {
+    pub fn palette(&self) -> Palette {
+        let mut palette = self
+            .colors
+            .map(Palette::new)
+            .or(self.palette)
+            .unwrap_or_default();
+
+        if let Some(foreground) = self.foreground {
+            palette.set_foreground(foreground);
+        }
+        if let Some(background) = self.background {
+            palette.set_background(background);
+        }
+        palette
+    }
+}
+
+/// ```toml
+/// [keymap]
+/// preset = "azerty"
+///
+/// [keymap.keys]
+/// 5 = ["W", "Up"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeymapConfig {
+    pub preset: Option<KeymapPreset>,
+    /// Host keys for each hex key, replacing the preset's binding
+    pub keys: HashMap<Chip8KeyCode, HostKeys>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum HostKeys {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl HostKeys {
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            HostKeys::One(name) => std::slice::from_ref(name),
+            HostKeys::Many(names) => names,
+        }
+    }
+}
+
+impl KeymapConfig {
+    pub fn keymap(&self) -> Keymap {
+        let mut keymap = Keymap::preset(self.preset.unwrap_or_default());
+        for (&key, host_keys) in &self.keys {
+            keymap.bind(key, host_keys.as_slice());
+        }
+        keymap
+    }
+
+    /// Applies `other` on top of this keymap. Choosing a preset starts over from it.
+    pub fn merge(&mut self, other: KeymapConfig) {
+        if other.preset.is_some() {
+            self.preset = other.preset;
+            self.keys.clear();
+        }
+        self.keys.extend(other.keys);
+    }
+}
+
+/// ```toml
+/// [gamepad]
+/// profile = "wasd"
+///
+/// [gamepad.buttons]
+/// 6 = ["a", "rightshoulder"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GamepadConfig {
+    pub profile: Option<GamepadProfile>,
+    /// Gamepad buttons for each hex key, replacing the profile's binding
+    pub buttons: HashMap<Chip8KeyCode, HostKeys>,
+}
+
+impl GamepadConfig {
+    pub fn button_map(&self) -> ButtonMap {
+        let mut button_map = ButtonMap::profile(self.profile.unwrap_or_default());
+        for (&key, buttons) in &self.buttons {
+            button_map.bind(key, buttons.as_slice());
+        }
+        button_map
+    }
+
+    /// Applies `other` on top of this mapping. Choosing a profile starts over from it.
+    pub fn merge(&mut self, other: GamepadConfig) {
+        if other.profile.is_some() {
+            self.profile = other.profile;
+            self.buttons.clear();
+        }
+        self.buttons.extend(other.buttons);
+    }
+}
+
+/// A quirks preset with individual quirks overridden.
+///
+/// ```toml
+/// [quirks]
+/// preset = "schip"
+/// key_wait_on_press = true
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuirksConfig {
+    pub preset: Option<QuirkPreset>,
+    pub shift_uses_vy: Option<bool>,
+    pub jump_uses_vx: Option<bool>,
+    pub key_wait_on_press: Option<bool>,
+    pub display_wait: Option<bool>,
+    pub clip_sprites: Option<bool>,
+    pub index_overflow_sets_vf: Option<bool>,
+    pub logic_resets_vf: Option<bool>,
+    pub load_store_increments_index: Option<bool>,
+}
+
+impl QuirksConfig {
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = Quirks::preset(self.preset.unwrap_or_default());
+        let overrides = [
+            (&mut quirks.shift_uses_vy, self.shift_uses_vy),
+            (&mut quirks.jump_uses_vx, self.jump_uses_vx),
+            (&mut quirks.key_wait_on_press, self.key_wait_on_press),
+            (&mut quirks.display_wait, self.display_wait),
+            (&mut quirks.clip_sprites, self.clip_sprites),
+            (&mut quirks.index_overflow_sets_vf, self.index_overflow_sets_vf),
+            (&mut quirks.logic_resets_vf, self.logic_resets_vf),
+            (&mut quirks.load_store_increments_index, self.load_store_increments_index),
+        ];
+        for (quirk, value) in overrides {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+        quirks
+    }
+
+    /// Applies `other` on top of these quirks. Choosing a preset starts over from it.
+    pub fn merge(&mut self, other: QuirksConfig) {
+        if other.preset.is_some() {
+            *self = other;
+            return;
+        }
+        self.shift_uses_vy = other.shift_uses_vy.or(self.shift_uses_vy);
+        self.jump_uses_vx = other.jump_uses_vx.or(self.jump_uses_vx);
+        self.key_wait_on_press = other.key_wait_on_press.or(self.key_wait_on_press);
+        self.display_wait = other.display_wait.or(self.display_wait);
+        self.clip_sprites = other.clip_sprites.or(self.clip_sprites);
+        self.index_overflow_sets_vf = other.index_overflow_sets_vf.or(self.index_overflow_sets_vf);
+        self.logic_resets_vf = other.logic_resets_vf.or(self.logic_resets_vf);
+        self.load_store_increments_index = other.load_store_increments_index.or(self.load_store_increments_index);
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Could not read {}: {e}", path.display()),
+            ConfigError::Parse(path, e) => write!(f, "Invalid config in {}: {e}", path.display()),
+        }
+    }
+}
+
+impl Config {
+    /// Loads `path`, or `DEFAULT_CONFIG` if no path is given and it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG).exists() => Path::new(DEFAULT_CONFIG),
+            None => return Ok(Self::default()),
+        };
+
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_owned(), e))
+    }
+
+    /// Applies the `[roms."<file name>"]` section for `rom`, if there is one.
+    pub fn apply_rom(&mut self, rom: &Path) {
+        let Some(name) = rom.file_name().and_then(|name| name.to_str()) else {
+            return;
+        };
+
+        if let Some(rom_config) = self.roms.remove(name) {
+            self.keymap.merge(rom_config.keymap);
+            self.gamepad.merge(rom_config.gamepad);
+            self.quirks.merge(rom_config.quirks);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::options::Options;
+
+    const CONFIG: &str = r#"
+        [quirks]
+        preset = "xochip"
+
+        [roms."game.ch8"]
+        quirks = { key_wait_on_press = true }
+        keymap = { keys = { "1" = "Q" } }
+    "#;
+
+    #[test]
+    fn rom_sections_apply_on_top_of_the_command_line() {
+        let mut config: Config = toml::from_str(CONFIG).unwrap();
+        let options = Options::parse_from(["chip8", "--quirks", "schip", "--keymap", "azerty", "game.ch8"]);
+        options.apply(&mut config);
+        config.apply_rom(&options.rom);
+
+        let quirks = config.quirks.quirks();
+        assert_eq!(config.quirks.preset, Some(QuirkPreset::Schip));
+        assert!(quirks.key_wait_on_press);
+        assert!(quirks.jump_uses_vx);
+        assert_eq!(config.keymap.preset, Some(KeymapPreset::Azerty));
+        assert_eq!(config.keymap.keys.len(), 1);
+    }
+
+    #[test]
+    fn rom_preset_replaces_the_command_line_preset() {
+        let mut config: Config = toml::from_str(CONFIG).unwrap();
+        config.roms.get_mut("game.ch8").unwrap().quirks.preset = Some(QuirkPreset::Chip8);
+        let options = Options::parse_from(["chip8", "--quirks", "schip", "game.ch8"]);
+        options.apply(&mut config);
+        config.apply_rom(&options.rom);
+
+        let quirks = config.quirks.quirks();
+        assert_eq!(config.quirks.preset, Some(QuirkPreset::Chip8));
+        assert!(quirks.key_wait_on_press);
+        assert!(!quirks.jump_uses_vx);
+    }
+
+    #[test]
+    fn other_roms_keep_the_command_line_settings() {
+        let mut config: Config = toml::from_str(CONFIG).unwrap();
+        let options = Options::parse_from(["chip8", "--quirks", "schip", "roms/other.ch8"]);
+        options.apply(&mut config);
+        config.apply_rom(&options.rom);
+
+        assert_eq!(config.quirks.preset, Some(QuirkPreset::Schip));
+        assert_eq!(config.quirks.key_wait_on_press, None);
+    }
+
+    #[test]
+    fn colours_on_the_command_line_override_the_palette() {
+        let mut config = Config::default();
+        let options = Options::parse_from(["chip8", "--palette", "lcd", "--fg", "#FF8000", "--bg", "102030", "game.ch8"]);
+        options.apply(&mut config);
+
+        let palette = config.display.palette();
+        assert_eq!(palette.color(1), Rgb(0xFF, 0x80, 0x00));
+        assert_eq!(palette.background(), Rgb(0x10, 0x20, 0x30));
+        assert_eq!(palette.color(2), Palette::LCD.color(2));
+
+        assert!(Options::try_parse_from(["chip8", "--fg", "F80", "game.ch8"]).is_err());
+        assert!(Options::try_parse_from(["chip8", "--palette", "sepia", "game.ch8"]).is_err());
+    }
+}
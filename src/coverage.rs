@@ -0,0 +1,283 @@
+//! Tracks how every byte of RAM was used, for ROM authors looking for dead
+//! code and stray data accesses.
+//!
+//! A byte is executed when it is part of a fetched instruction, read when
+//! DXYN or FX65 load it through I, and written by FX33 or FX55.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use crate::{
+    cpu::{CPU, RAM},
+    disasm::Instruction,
+};
+
+const PROGRAM_START: usize = 0x200;
+
+/// Bytes per row of the heatmap, and the size of each byte in pixels.
+const MAP_COLUMNS: usize = 64;
+const MAP_SCALE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Execute,
+    Read,
+    Write,
+}
+
+const ACCESSES: [Access; 3] = [Access::Execute, Access::Read, Access::Write];
+
+#[derive(Debug)]
+pub struct Coverage {
+    rom_len: usize,
+    executed: Vec<u64>,
+    read: Vec<u64>,
+    written: Vec<u64>,
+}
+
+impl Coverage {
+    /// `rom_len` bytes from 0x200 are listed in the report.
+    pub fn new(rom_len: usize) -> Self {
+        Self {
+            rom_len: rom_len.min(RAM - PROGRAM_START),
+            executed: vec![0; RAM],
+            read: vec![0; RAM],
+            written: vec![0; RAM],
+        }
+    }
+
+    /// Counts the accesses of the instruction `cpu` is about to execute.
+    pub(crate) fn record(&mut self, cpu: &CPU) {
+        let pc = cpu.program_counter();
+        self.count(Access::Execute, pc, 2);
+
+        let index = cpu.index_register() as usize;
+        match Instruction::decode(cpu.peek_opcode()) {
+            Instruction::Drw(_, _, n) => self.count(Access::Read, index, n as usize),
+            Instruction::Load(x) => self.count(Access::Read, index, x as usize + 1),
+            Instruction::LdBcd(_) => self.count(Access::Write, index, 3),
+            Instruction::Store(x) => self.count(Access::Write, index, x as usize + 1),
+            _ => {}
+        }
+    }
+
+    fn count(&mut self, access: Access, start: usize, len: usize) {
+        let counts = match access {
+            Access::Execute => &mut self.executed,
+            Access::Read => &mut self.read,
+            Access::Write => &mut self.written,
+        };
+        // Out of range accesses fault in the CPU, there is nothing to count
+        for count in counts.iter_mut().skip(start).take(len) {
+            *count += 1;
+        }
+    }
+
+    fn counts(&self, access: Access) -> &[u64] {
+        match access {
+            Access::Execute => &self.executed,
+            Access::Read => &self.read,
+            Access::Write => &self.written,
+        }
+    }
+
+    // Colour of every byte: red when executed, green when read and blue when
+    // written, brighter the more often it happened
+    fn colors(&self) -> Vec<[u8; 3]> {
+        let max = ACCESSES.map(|access| self.counts(access).iter().copied().max().unwrap_or(0));
+        (0..RAM)
+            .map(|address| {
+                let mut color = [24; 3];
+                for (channel, access) in ACCESSES.into_iter().enumerate() {
+                    let count = self.counts(access)[address];
+                    if count > 0 {
+                        let heat = (count as f64).ln_1p() / (max[channel] as f64).ln_1p();
+                        color[channel] = (90.0 + 165.0 * heat) as u8;
+                    }
+                }
+                color
+            })
+            .collect()
+    }
+
+    /// Writes the 4 KiB of RAM as a binary PPM image, one square per byte and
+    /// 64 bytes per row.
+    pub fn write_heatmap(&self, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = (MAP_COLUMNS * MAP_SCALE, RAM / MAP_COLUMNS * MAP_SCALE);
+        write!(out, "P6\n{width} {height}\n255\n")?;
+
+        let colors = self.colors();
+        let mut row = Vec::with_capacity(width * 3);
+        for line in colors.chunks(MAP_COLUMNS) {
+            row.clear();
+            for color in line {
+                for _ in 0..MAP_SCALE {
+                    row.extend_from_slice(color);
+                }
+            }
+            for _ in 0..MAP_SCALE {
+                out.write_all(&row)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes an HTML page with a summary, the heatmap and the ROM's
+    /// disassembly annotated with access counts.
+    pub fn write_html(&self, cpu: &CPU, title: &str, out: &mut impl Write) -> io::Result<()> {
+        let rom = PROGRAM_START..PROGRAM_START + self.rom_len;
+        let touched = |access: Access| self.counts(access)[rom.clone()].iter().filter(|&&count| count > 0).count();
+        let unused = rom
+            .clone()
+            .filter(|&address| ACCESSES.iter().all(|&access| self.counts(access)[address] == 0))
+            .count();
+
+        writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">")?;
+        writeln!(out, "<title>Coverage of {}</title>", escape(title))?;
+        writeln!(
+            out,
+            "<style>\n\
+             body {{ background: #181818; color: #ddd; font-family: monospace; }}\n\
+             .map {{ display: grid; grid-template-columns: repeat({MAP_COLUMNS}, 8px); gap: 1px; margin: 1em 0; }}\n\
+             .map div {{ width: 8px; height: 8px; }}\n\
+             table {{ border-collapse: collapse; }}\n\
+             td, th {{ padding: 0 1em 0 0; text-align: left; }}\n\
+             td.n {{ text-align: right; }}\n\
+             tr.unused {{ color: #666; }}\n\
+             </style>\n</head>\n<body>"
+        )?;
+
+        writeln!(out, "<h1>{}</h1>", escape(title))?;
+        writeln!(
+            out,
+            "<p>{} ROM bytes: {} executed, {} read, {} written, {} never touched.</p>",
+            self.rom_len,
+            touched(Access::Execute),
+            touched(Access::Read),
+            touched(Access::Write),
+            unused
+        )?;
+        writeln!(
+            out,
+            "<p>Each square is a byte of RAM, {MAP_COLUMNS} per row from 0x000. \
+             Red is executed, green read and blue written.</p>"
+        )?;
+
+        let mut map = String::from("<div class=\"map\">");
+        for (address, [r, g, b]) in self.colors().into_iter().enumerate() {
+            let _ = write!(map, "<div title=\"{address:03X}\" style=\"background:#{r:02x}{g:02x}{b:02x}\"></div>");
+        }
+        map.push_str("</div>");
+        writeln!(out, "{map}")?;
+
+        writeln!(
+            out,
+            "<table>\n<tr><th>addr</th><th>bytes</th><th>instruction</th>\
+             <th>executed</th><th>read</th><th>written</th></tr>"
+        )?;
+        let memory = cpu.memory();
+        let mut address = rom.start;
+        while address < rom.end {
+            let read = self.read[address];
+            let written = self.written[address];
+            let data = read > 0 || written > 0;
+
+            // Data bytes are listed one at a time, so code after odd sized data stays aligned
+            let (bytes, listing) = if (self.executed[address] > 0 || !data) && address + 1 < rom.end {
+                let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+                (format!("{opcode:04X}"), Instruction::decode(opcode).to_string())
+            } else {
+                (format!("{:02X}", memory[address]), format!("DB {:#04X}", memory[address]))
+            };
+
+            let class = if self.executed[address] == 0 && !data { " class=\"unused\"" } else { "" };
+            writeln!(
+                out,
+                "<tr{class}><td>{address:03X}</td><td>{bytes}</td><td>{}</td>\
+                 <td class=\"n\">{}</td><td class=\"n\">{read}</td><td class=\"n\">{written}</td></tr>",
+                escape(&listing),
+                self.executed[address]
+            )?;
+            address += bytes.len() / 2;
+        }
+        writeln!(out, "</table>\n</body>\n</html>")
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Quirks;
+
+    // 200: I = 20E, 202: draw 1 byte, 204: I = 300, 206: BCD of V1,
+    // 208: load V0-V1, 20A: store V0-V1, 20C: jump 20F, 20E: sprite byte,
+    // 20F: jump 20F, an instruction at an odd address after the data
+    const ROM: [u8; 17] = [
+        0xA2, 0x0E, 0xD0, 0x01, 0xA3, 0x00, 0xF1, 0x33, 0xF1, 0x65, 0xF1, 0x55, 0x12, 0x0F, 0xFF, 0x12, 0x0F,
+    ];
+
+    fn run(instructions: usize) -> (CPU, Coverage) {
+        let mut cpu = CPU::with_quirks(Quirks::default());
+        cpu.load_rom(&ROM);
+        cpu.set_coverage(Some(Coverage::new(ROM.len())));
+        for _ in 0..instructions {
+            cpu.cycle().unwrap();
+        }
+        let coverage = cpu.take_coverage().unwrap();
+        (cpu, coverage)
+    }
+
+    #[test]
+    fn counts_executes_reads_and_writes() {
+        let (_, coverage) = run(9);
+
+        assert_eq!(coverage.executed[0x200..0x20E], [1; 14]);
+        assert_eq!(coverage.executed[0x20E], 0);
+        assert_eq!(coverage.executed[0x20F..0x211], [2, 2]);
+
+        // DXYN and FX65 read through I
+        assert_eq!(coverage.read[0x20E], 1);
+        assert_eq!(coverage.read[0x300..0x303], [1, 1, 0]);
+        // FX33 writes three digits, FX55 one byte per register from where FX65 left I
+        assert_eq!(coverage.written[0x300..0x304], [1, 1, 2, 1]);
+        assert_eq!(coverage.written.iter().sum::<u64>(), 5);
+    }
+
+    #[test]
+    fn heatmap_is_a_ppm_of_all_ram() {
+        let (_, coverage) = run(9);
+        let mut out = Vec::new();
+        coverage.write_heatmap(&mut out).unwrap();
+
+        let header = b"P6\n512 512\n255\n";
+        assert!(out.starts_with(header));
+        assert_eq!(out.len(), header.len() + 512 * 512 * 3);
+        // 0x200 is the first square of row 8, executed only
+        let pixel = header.len() + (8 * MAP_SCALE * 512) * 3;
+        assert!(out[pixel] > 24 && out[pixel + 1] == 24 && out[pixel + 2] == 24);
+    }
+
+    #[test]
+    fn listing_stays_aligned_around_data() {
+        let (cpu, coverage) = run(9);
+        let mut out = Vec::new();
+        coverage.write_html(&cpu, "<test>", &mut out).unwrap();
+        let html = String::from_utf8(out).unwrap();
+
+        assert!(html.contains("<title>Coverage of &lt;test&gt;</title>"));
+        assert!(html.contains("17 ROM bytes: 16 executed, 1 read, 0 written, 0 never touched."));
+        let row = |address: &str, bytes: &str, listing: &str| {
+            format!("<tr><td>{address}</td><td>{bytes}</td><td>{listing}</td>")
+        };
+        assert!(html.contains(&row("20C", "120F", "JP 0x20F")), "{html}");
+        assert!(html.contains(&row("20E", "FF", "DB 0xFF")), "{html}");
+        assert!(html.contains(&row("20F", "120F", "JP 0x20F")), "{html}");
+        assert!(!html.contains("<td>210</td>"), "{html}");
+    }
+}
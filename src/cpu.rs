@@ -1,17 +1,34 @@
 #![allow(unused_variables)]
 
 use rand::{thread_rng, Rng};
-use std::{fs::File, io::Read};
+use std::ops::Range;
 
+use crate::coverage::Coverage;
 use crate::font::{FONT, SPRITE_WIDTH};
+use crate::profiler::Profiler;
+use crate::tracer::Tracer;
 
+mod error;
+mod quirks;
+mod savestate;
 mod stack;
+pub use error::CpuError;
+pub use quirks::{QuirkPreset, Quirks};
 use stack::Stack;
 
-const RAM: usize = 4096;
+pub const RAM: usize = 4096;
 pub const CHIP8_WIDTH: usize = 64;
 pub const CHIP8_HEIGHT: usize = 32;
-const KEY_COUNT: usize = 16;
+pub const KEY_COUNT: usize = 16;
+// SUPER-CHIP allows 16 nested subroutines, the VIP only had room for 12
+const STACK_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuState {
+    Running,
+    /// Halted on FX0A, `pressed` is the key being held down until it is released
+    WaitingForKey { register: u8, pressed: Option<u8> },
+}
 
 #[derive(Debug)]
 pub struct CPU {
@@ -24,22 +41,30 @@ pub struct CPU {
     sound_timer: u8,
     var_registers: [u8; 16],
     keys: [bool; KEY_COUNT],
-    super_chip: bool,
+    quirks: Quirks,
+    state: CpuState,
+    tracer: Option<Box<Tracer>>,
+    profiler: Option<Box<Profiler>>,
+    coverage: Option<Box<Coverage>>,
 }
 
 impl CPU {
-    pub fn new(super_chip: bool) -> Self {
+    pub fn with_quirks(quirks: Quirks) -> Self {
         let mut cpu = CPU {
             program_counter: 0x200,
             index_register: 0,
             vram: [[0; CHIP8_WIDTH]; CHIP8_HEIGHT],
-            stack: Stack::new(),
+            stack: Stack::new(STACK_DEPTH),
             ram: [0; RAM],
             delay_timer: 0,
             sound_timer: 0,
             var_registers: [0; 16],
             keys: [false; KEY_COUNT],
-            super_chip
+            quirks,
+            state: CpuState::Running,
+            tracer: None,
+            profiler: None,
+            coverage: None,
         };
 
         cpu.ram[..80].copy_from_slice(&FONT);
@@ -50,21 +75,110 @@ impl CPU {
         &self.vram
     }
 
-    pub fn load_rom_in_ram(&mut self, mut file: File) {
-        file.read(&mut self.ram[512..RAM]).unwrap();
+    pub fn state(&self) -> CpuState {
+        self.state
+    }
+
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    pub fn set_program_counter(&mut self, pc: usize) {
+        self.program_counter = pc;
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    pub fn set_index_register(&mut self, index: u16) {
+        self.index_register = index;
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.var_registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut [u8; 16] {
+        &mut self.var_registers
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// Return addresses of the subroutines being run, innermost last.
+    pub fn stack(&self) -> &[usize] {
+        self.stack.as_slice()
+    }
+
+    pub fn keys(&self) -> &[bool; KEY_COUNT] {
+        &self.keys
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer.map(Box::new);
+    }
+
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler.map(Box::new);
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take().map(|profiler| *profiler)
+    }
+
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage.map(Box::new);
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take().map(|coverage| *coverage)
+    }
+
+    /// The instruction `cycle` will execute next.
+    pub fn peek_opcode(&self) -> u16 {
+        let pc = self.program_counter % RAM;
+        ((self.ram[pc] as u16) << 8) | self.ram[(pc + 1) % RAM] as u16
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    /// Copies a ROM image to 0x200, anything that doesn't fit in RAM is dropped.
+    pub fn load_rom(&mut self, rom: &[u8]) {
+        let len = rom.len().min(RAM - 512);
+        self.ram[512..512 + len].copy_from_slice(&rom[..len]);
     }
 
     pub fn keypress(&mut self, input: usize, pressed: bool) {
         self.keys[input] = pressed;
-        //println!("pressed: {}, {}", input, self.keys[input]);
     }
 
-    fn fetch(&mut self) -> u16 {
-        if self.program_counter >= RAM {
-            panic!(
-                "PC jumped out of range of RAM: {0} >= {RAM}",
-                self.program_counter
-            );
+    fn fetch(&mut self) -> Result<u16, CpuError> {
+        if self.program_counter + 1 >= RAM {
+            return Err(CpuError::PcOutOfRange { pc: self.program_counter });
         }
 
         let instruction_one = self.ram[self.program_counter];
@@ -72,10 +186,17 @@ impl CPU {
 
         let final_instruction = ((instruction_one as u16) << 8) | instruction_two as u16;
         self.program_counter += 2;
-        final_instruction
+        Ok(final_instruction)
     }
 
     pub fn cycle_timers(&mut self) -> u8 {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.end_frame();
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.end_frame();
+        }
+
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -87,16 +208,60 @@ impl CPU {
         self.sound_timer
     }
 
-    pub fn cycle(&mut self) {
-        let opcode = self.fetch();
-        self.decode(opcode);
+    /// Executes one instruction and returns its opcode.
+    pub fn cycle(&mut self) -> Result<u16, CpuError> {
+        if let Some(mut profiler) = self.profiler.take() {
+            profiler.record(self);
+            self.profiler = Some(profiler);
+        }
+        if let Some(mut coverage) = self.coverage.take() {
+            coverage.record(self);
+            self.coverage = Some(coverage);
+        }
+
+        // Taken out while the instruction runs so it can look at the CPU
+        let Some(mut tracer) = self.tracer.take() else {
+            return self.execute();
+        };
+
+        tracer.before_instruction(self);
+        let result = self.execute();
+        match &result {
+            Ok(_) => tracer.after_instruction(self),
+            Err(e) => tracer.error(e),
+        }
+        self.tracer = Some(tracer);
+        result
     }
 
-    fn decode(&mut self, opcode: u16) {
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1
+    fn execute(&mut self) -> Result<u16, CpuError> {
+        let opcode = self.fetch()?;
+        // Faults are raised before any state changes, so stepping back leaves
+        // the CPU on the instruction that caused it
+        if let Err(e) = self.decode(opcode) {
+            self.program_counter -= 2;
+            return Err(e);
+        }
+        Ok(opcode)
+    }
+
+    // Address of the instruction being executed
+    fn current_pc(&self) -> usize {
+        self.program_counter - 2
+    }
+
+    // The `len` bytes of RAM starting at `start`, if they all exist
+    fn ram_range(&self, start: usize, len: usize) -> Result<Range<usize>, CpuError> {
+        if start + len > RAM {
+            return Err(CpuError::MemoryOutOfBounds {
+                pc: self.current_pc(),
+                address: start.max(RAM),
+            });
         }
+        Ok(start..start + len)
+    }
 
+    fn decode(&mut self, opcode: u16) -> Result<(), CpuError> {
         let instruction = (
             (0xF000 & opcode) >> 12,
             (0x0F00 & opcode) >> 8,
@@ -104,8 +269,6 @@ impl CPU {
             (0x000F & opcode),
         );
 
-        // println!("Opcode {:X}", opcode);
-
         let nn: u8 = (0x00FF & opcode).try_into().unwrap();
         let nnn: u16 = 0x0FFF & opcode;
 
@@ -116,8 +279,10 @@ impl CPU {
         match instruction {
             (0x0, 0x0, 0xe, 0x0) => self.op_00e0(),
             (0x1, _, _, _) => self.op_1nnn(nnn),
-            (0x0, 0x0, 0xe, 0xe) => self.op_00ee(),
-            (0x2, _, _, _) => self.op_2nnn(nnn),
+            (0x0, 0x0, 0xe, 0xe) => self.op_00ee()?,
+            // 0NNN calls machine code on the VIP, which can't run here, so it's skipped
+            (0x0, _, _, _) => {}
+            (0x2, _, _, _) => self.op_2nnn(nnn)?,
             (0x3, _, _, _) => self.op_3xnn(x, nn),
             (0x4, _, _, _) => self.op_4xnn(x, nn),
             (0x5, _, _, 0) => self.op_5xy0(x, y),
@@ -136,7 +301,7 @@ impl CPU {
             (0xa, _, _, _) => self.op_annn(nnn),
             (0xb, _, _, _) => self.op_bnnn(x, nnn),
             (0xc, _, _, _) => self.op_cxnn(x, nn),
-            (0xd, _, _, _) => self.op_dxyn(x, y, n),
+            (0xd, _, _, _) => self.op_dxyn(x, y, n)?,
             (0xf, _, 0, 7) => self.op_fx07(x),
             (0xf, _, 1, 5) => self.op_fx15(x),
             (0xf, _, 1, 8) => self.op_fx18(x),
@@ -145,18 +310,17 @@ impl CPU {
             (0xf, _, 0, 0xa) => self.op_fx0a(x),
             (0xf, _, 1, 0xe) => self.op_fx1e(x),
             (0xf, _, 2, 9) => self.op_fx29(x),
-            (0xf, _, 3, 3) => self.op_fx33(x),
-            (0xf, _, 5, 5) => self.op_fx55(x),
-            (0xf, _, 6, 5) => self.op_fx65(x),
-            _ => self.unknown_instruction(instruction),
+            (0xf, _, 3, 3) => self.op_fx33(x)?,
+            (0xf, _, 5, 5) => self.op_fx55(x)?,
+            (0xf, _, 6, 5) => self.op_fx65(x)?,
+            _ => {
+                return Err(CpuError::UnknownOpcode {
+                    pc: self.current_pc(),
+                    opcode,
+                })
+            }
         };
-    }
-
-    fn unknown_instruction(&mut self, instruction: (u16, u16, u16, u16)) {
-        // println!(
-        //     "Instruction: {:X}{:X}{:X}{:X} not implemented",
-        //     instruction.0, instruction.1, instruction.2, instruction.3
-        // );
+        Ok(())
     }
 
     // Clear screen
@@ -172,20 +336,24 @@ impl CPU {
         self.program_counter = nnn as usize;
     }
     // Run subroutine
-    fn op_2nnn(&mut self, nnn: u16) {
-        self.stack.push(self.program_counter);
+    fn op_2nnn(&mut self, nnn: u16) -> Result<(), CpuError> {
+        self.stack
+            .push(self.program_counter)
+            .map_err(|_| CpuError::StackOverflow { pc: self.current_pc() })?;
         self.program_counter = nnn as usize;
-        // println!("nnn: {nnn}");
-        // println!("In Ram here: {}", self.ram[nnn as usize]);
+        Ok(())
     }
     // Return from subroutine
-    fn op_00ee(&mut self) {
-        self.program_counter = self.stack.pop().unwrap();
+    fn op_00ee(&mut self) -> Result<(), CpuError> {
+        self.program_counter = self
+            .stack
+            .pop()
+            .ok_or(CpuError::StackUnderflow { pc: self.current_pc() })?;
+        Ok(())
     }
     // Skip
     fn op_3xnn(&mut self, x: u8, nn: u8) {
         let val = self.var_registers[x as usize];
-        // println!("{val} {nn}");
         if val == nn {
             self.program_counter += 2;
         }
@@ -219,10 +387,7 @@ impl CPU {
     }
     // Add
     fn op_7xnn(&mut self, x: u8, nn: u8) {
-        let (sum, overflow) = (self.var_registers[x as usize]).overflowing_add(nn);
-        self.var_registers[x as usize] = sum;
-        // Carry flag?
-        // *self.var_registers.last_mut().unwrap() = u8::from(overflow);
+        self.var_registers[x as usize] = self.var_registers[x as usize].wrapping_add(nn);
     }
 
     // Set
@@ -234,16 +399,26 @@ impl CPU {
     fn op_8xy1(&mut self, x: u8, y: u8) {
         let val_vx = self.var_registers[x as usize];
         self.var_registers[x as usize] = val_vx | self.var_registers[y as usize];
+        self.reset_vf_after_logic();
     }
 
     fn op_8xy2(&mut self, x: u8, y: u8) {
         let val_vx = self.var_registers[x as usize];
         self.var_registers[x as usize] = val_vx & self.var_registers[y as usize];
+        self.reset_vf_after_logic();
     }
 
     fn op_8xy3(&mut self, x: u8, y: u8) {
         let val_vx = self.var_registers[x as usize];
         self.var_registers[x as usize] = val_vx ^ self.var_registers[y as usize];
+        self.reset_vf_after_logic();
+    }
+
+    // The VIP routines for these ops leave VF cleared
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            *self.var_registers.last_mut().unwrap() = 0;
+        }
     }
 
     // Add
@@ -272,7 +447,7 @@ impl CPU {
     }
 
     fn op_8xy6(&mut self, x: u8, y: u8) {
-        if self.super_chip {
+        if self.quirks.shift_uses_vy {
             self.var_registers[x as usize] = self.var_registers[y as usize];
         }
         let vx = self.var_registers[x as usize];
@@ -284,7 +459,7 @@ impl CPU {
     }
 
     fn op_8xye(&mut self, x: u8, y: u8) {
-        if self.super_chip {
+        if self.quirks.shift_uses_vy {
             self.var_registers[x as usize] = self.var_registers[y as usize];
         }
         let vx = self.var_registers[x as usize];
@@ -300,26 +475,28 @@ impl CPU {
         let vx = self.var_registers[x as usize];
         let vy = self.var_registers[y as usize];
         let (sub, underflow) = vy.overflowing_sub(vx);
+        self.var_registers[x as usize] = sub;
+        // Flag last, so it wins when X is F
         if underflow {
             *self.var_registers.last_mut().unwrap() = 0;
         } else {
             *self.var_registers.last_mut().unwrap() = 1;
         }
-        self.var_registers[x as usize] = sub;
     }
 
     fn op_annn(&mut self, nnn: u16) {
-        self.index_register = nnn as u16;
+        self.index_register = nnn;
     }
 
+    // Jump with offset. SUPER-CHIP reads it as BXNN and adds VX instead of V0,
+    // the high nibble of NNN is X itself so the target stays within its page.
     fn op_bnnn(&mut self, x: u8, nnn: u16) {
-        let v0 = self.var_registers[0];
-        let mut jump_loc = v0 + nnn as u8;
-
-        if self.super_chip {
-            jump_loc = self.var_registers[x as usize] + nnn as u8;
-        }
-        self.program_counter = jump_loc as usize;
+        let offset = if self.quirks.jump_uses_vx {
+            self.var_registers[x as usize]
+        } else {
+            self.var_registers[0]
+        };
+        self.program_counter = (nnn + offset as u16) as usize & 0xFFF;
     }
 
     fn op_cxnn(&mut self, x: u8, nn: u8) {
@@ -341,87 +518,120 @@ impl CPU {
         self.sound_timer = self.var_registers[x as usize];
     }
 
+    // Only the low nibble of VX selects a key
     fn op_ex9e(&mut self, x: u8) {
-        let vx = self.var_registers[x as usize];
+        let vx = self.var_registers[x as usize] & 0xF;
         if self.keys[vx as usize] {
             self.program_counter += 2;
         }
     }
 
     fn op_exa1(&mut self, x: u8) {
-        let vx = self.var_registers[x as usize];
+        let vx = self.var_registers[x as usize] & 0xF;
         if !self.keys[vx as usize] {
             self.program_counter += 2;
         }
     }
 
     fn op_fx1e(&mut self, x: u8) {
-        self.index_register += self.var_registers[x as usize] as u16;
-        if self.index_register > 0x1000 {
-            *self.var_registers.last_mut().unwrap() = 1;
+        self.index_register = self.index_register.wrapping_add(self.var_registers[x as usize] as u16);
+        if self.quirks.index_overflow_sets_vf {
+            *self.var_registers.last_mut().unwrap() = (self.index_register > 0xFFF) as u8;
         }
     }
 
+    // Wait for a key. On the VIP the key is only reported once it is released,
+    // so holding it down doesn't satisfy several FX0A in a row.
     fn op_fx0a(&mut self, x: u8) {
-        let mut key_pressed = false;
-        for (key, pressed) in self.keys.iter().enumerate() {
-            if *pressed {
-                self.var_registers[x as usize] = key as u8;
-                key_pressed = true;
-                break;
+        let held = self.keys.iter().position(|&pressed| pressed).map(|key| key as u8);
+
+        let pressed = match self.state {
+            CpuState::WaitingForKey { pressed: Some(key), .. } => Some(key),
+            _ => held,
+        };
+
+        let done = match pressed {
+            Some(key) if self.quirks.key_wait_on_press || !self.keys[key as usize] => {
+                self.var_registers[x as usize] = key;
+                true
             }
-        }
-        if !key_pressed {
+            _ => false,
+        };
+
+        if done {
+            self.state = CpuState::Running;
+        } else {
+            self.state = CpuState::WaitingForKey { register: x, pressed };
             self.program_counter -= 2;
         }
     }
 
     fn op_fx29(&mut self, x: u8) {
-        let character = self.var_registers[x as usize];
-        // println!("Current char select {:X}", character);
+        let character = self.var_registers[x as usize] & 0xF;
         self.index_register = (SPRITE_WIDTH * character) as u16;
     }
 
-    fn op_fx33(&mut self, x: u8) {
+    fn op_fx33(&mut self, x: u8) -> Result<(), CpuError> {
         let vx = self.var_registers[x as usize];
         let third = vx % 10;
         let second = (vx / 10) % 10;
         let first = vx / 100;
 
-        let i = self.index_register as usize;
-        self.ram[i] = first;
-        self.ram[i + 1] = second;
-        self.ram[i + 2] = third;
+        let range = self.ram_range(self.index_register as usize, 3)?;
+        self.ram[range].copy_from_slice(&[first, second, third]);
+        Ok(())
     }
 
-    fn op_fx55(&mut self, x: u8) {
-        let index = self.index_register;
-        for i in 0..=x {
-            let total = index + i as u16;
-            //     println!("FX55: {} {} {}", total, val, i);
-            self.ram[total as usize] = self.var_registers[i as usize] as u8;
-        }
+    fn op_fx55(&mut self, x: u8) -> Result<(), CpuError> {
+        let count = x as usize + 1;
+        let range = self.ram_range(self.index_register as usize, count)?;
+        self.ram[range].copy_from_slice(&self.var_registers[..count]);
+        self.advance_index_after_load_store(count);
+        Ok(())
     }
 
-    fn op_fx65(&mut self, x: u8) {
-        let index = self.index_register;
-        for i in 0..=x {
-            let total = index + i as u16;
-            //     println!("FX55: {} {} {}", total, val, i);
-            self.var_registers[i as usize] = self.ram[total as usize] as u8;
+    fn op_fx65(&mut self, x: u8) -> Result<(), CpuError> {
+        let count = x as usize + 1;
+        let range = self.ram_range(self.index_register as usize, count)?;
+        self.var_registers[..count].copy_from_slice(&self.ram[range]);
+        self.advance_index_after_load_store(count);
+        Ok(())
+    }
+
+    fn advance_index_after_load_store(&mut self, count: usize) {
+        if self.quirks.load_store_increments_index {
+            self.index_register = self.index_register.wrapping_add(count as u16);
         }
     }
 
-    fn op_dxyn(&mut self, x: u8, y: u8, n: u8) {
+    fn op_dxyn(&mut self, x: u8, y: u8, n: u8) -> Result<(), CpuError> {
+        // Only the starting position wraps around the screen. Read it before
+        // clearing VF, X or Y may well be F.
+        let start_x = self.var_registers[x as usize] as usize % CHIP8_WIDTH;
+        let start_y = self.var_registers[y as usize] as usize % CHIP8_HEIGHT;
+        let sprite = self.ram_range(self.index_register as usize, n as usize)?;
         *self.var_registers.last_mut().unwrap() = 0;
+
         for byte in 0..n {
-            let y_coord = ((self.var_registers[(y) as usize] + byte)as usize) % CHIP8_HEIGHT;
+            let mut y_coord = start_y + byte as usize;
+            if y_coord >= CHIP8_HEIGHT {
+                if self.quirks.clip_sprites {
+                    break;
+                }
+                y_coord %= CHIP8_HEIGHT;
+            }
 
             // nth byte of sprite data
-            let nth_byte = self.ram[(self.index_register + byte as u16) as usize];
+            let nth_byte = self.ram[sprite.start + byte as usize];
 
             for bit in 0..8 {
-                let x_coord = (self.var_registers[(x) as usize] as usize + bit) % CHIP8_WIDTH;
+                let mut x_coord = start_x + bit;
+                if x_coord >= CHIP8_WIDTH {
+                    if self.quirks.clip_sprites {
+                        break;
+                    }
+                    x_coord %= CHIP8_WIDTH;
+                }
 
                 // From most to least significant bit
                 let mlsb = nth_byte >> (7 - bit) & 1;
@@ -434,5 +644,9 @@ impl CPU {
                 self.vram[y_coord][x_coord] ^= mlsb;
             }
         }
+        Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,32 @@
+use std::{error, fmt};
+
+/// Why the CPU stopped executing a ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    /// 00EE with no subroutine to return from
+    StackUnderflow { pc: usize },
+    /// 2NNN nested deeper than the stack allows
+    StackOverflow { pc: usize },
+    /// The program counter left RAM
+    PcOutOfRange { pc: usize },
+    /// An instruction read or wrote past the end of RAM
+    MemoryOutOfBounds { pc: usize, address: usize },
+    /// An opcode that isn't a CHIP-8 instruction
+    UnknownOpcode { pc: usize, opcode: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::StackUnderflow { pc } => write!(f, "{pc:#05X}: return with an empty stack"),
+            CpuError::StackOverflow { pc } => write!(f, "{pc:#05X}: subroutine calls nested too deep"),
+            CpuError::PcOutOfRange { pc } => write!(f, "program counter jumped out of RAM: {pc:#X}"),
+            CpuError::MemoryOutOfBounds { pc, address } => {
+                write!(f, "{pc:#05X}: memory access out of bounds at {address:#X}")
+            }
+            CpuError::UnknownOpcode { pc, opcode } => write!(f, "{pc:#05X}: unknown opcode {opcode:04X}"),
+        }
+    }
+}
+
+impl error::Error for CpuError {}
@@ -0,0 +1,102 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+/// Behaviours that differ between CHIP-8 interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE copy VY into VX before shifting
+    pub shift_uses_vy: bool,
+    /// BNNN jumps to VX + NNN instead of V0 + NNN
+    pub jump_uses_vx: bool,
+    /// FX0A continues as soon as a key is down instead of waiting for it to be released
+    pub key_wait_on_press: bool,
+    /// DXYN waits for the display interrupt, so at most one sprite is drawn per frame.
+    /// Off in every preset, as it slows most games down at a fixed instructions per frame;
+    /// VIP timing always waits.
+    pub display_wait: bool,
+    /// Sprites are cut off at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    /// FX1E sets VF when I goes past 0xFFF and clears it otherwise, as on the Amiga interpreter
+    pub index_overflow_sets_vf: bool,
+    /// 8XY1/8XY2/8XY3 clear VF after the operation, as on the VIP
+    pub logic_resets_vf: bool,
+    /// FX55/FX65 leave I pointing past the last register stored or loaded
+    pub load_store_increments_index: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum QuirkPreset {
+    /// The original COSMAC VIP interpreter
+    #[default]
+    Chip8,
+    /// SUPER-CHIP 1.1 on the HP 48
+    Schip,
+    /// XO-CHIP, as in Octo, where sprites wrap around the screen edges
+    XoChip,
+}
+
+impl Quirks {
+    pub fn preset(preset: QuirkPreset) -> Self {
+        match preset {
+            QuirkPreset::Chip8 => Self {
+                shift_uses_vy: true,
+                jump_uses_vx: false,
+                key_wait_on_press: false,
+                display_wait: false,
+                clip_sprites: true,
+                index_overflow_sets_vf: false,
+                logic_resets_vf: true,
+                load_store_increments_index: true,
+            },
+            QuirkPreset::Schip => Self {
+                shift_uses_vy: false,
+                jump_uses_vx: true,
+                key_wait_on_press: false,
+                display_wait: false,
+                clip_sprites: true,
+                index_overflow_sets_vf: false,
+                logic_resets_vf: false,
+                load_store_increments_index: false,
+            },
+            QuirkPreset::XoChip => Self {
+                shift_uses_vy: true,
+                jump_uses_vx: false,
+                key_wait_on_press: false,
+                display_wait: false,
+                clip_sprites: false,
+                index_overflow_sets_vf: false,
+                logic_resets_vf: false,
+                load_store_increments_index: true,
+            },
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::preset(QuirkPreset::default())
+    }
+}
+
+impl FromStr for QuirkPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" | "vip" => Ok(QuirkPreset::Chip8),
+            "schip" | "superchip" => Ok(QuirkPreset::Schip),
+            "xochip" | "xo-chip" => Ok(QuirkPreset::XoChip),
+            _ => Err(format!("unknown quirks preset '{s}', expected chip8, schip or xochip")),
+        }
+    }
+}
+
+impl TryFrom<String> for QuirkPreset {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
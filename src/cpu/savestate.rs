@@ -0,0 +1,145 @@
+//! Save states in a small fixed size binary format, big endian like the
+//! CHIP-8 itself:
+//!
+//! ```text
+//! "CH8S" version:u8
+//! pc:u16 i:u16 v0-vf:[u8; 16] dt:u8 st:u8
+//! depth:u8 stack:[u16; 16]      unused entries are 0
+//! keys:u16                      bit n set while key n is down
+//! waiting:u8 register:u8 key:u8 FX0A state, key is FF when none is held
+//! ram:[u8; 4096]
+//! vram:[u8; 256]                one bit per pixel, rows left to right
+//! ```
+//!
+//! Quirks aren't saved, they come from the configuration of the session
+//! loading the state.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use super::{CpuState, Stack, CHIP8_HEIGHT, CHIP8_WIDTH, CPU, KEY_COUNT, RAM, STACK_DEPTH};
+
+const MAGIC: &[u8; 4] = b"CH8S";
+const VERSION: u8 = 1;
+const NO_KEY: u8 = 0xFF;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("invalid save state: {message}"))
+}
+
+fn read_u8(input: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_u16(input: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    input.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+impl CPU {
+    /// Writes everything needed to resume execution later.
+    pub fn save_state(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+
+        out.write_all(&(self.program_counter as u16).to_be_bytes())?;
+        out.write_all(&self.index_register.to_be_bytes())?;
+        out.write_all(&self.var_registers)?;
+        out.write_all(&[self.delay_timer, self.sound_timer])?;
+
+        let stack = self.stack.as_slice();
+        out.write_all(&[stack.len() as u8])?;
+        for slot in 0..STACK_DEPTH {
+            let address = stack.get(slot).copied().unwrap_or(0) as u16;
+            out.write_all(&address.to_be_bytes())?;
+        }
+
+        let keys = (0..KEY_COUNT).filter(|&key| self.keys[key]).fold(0u16, |keys, key| keys | 1 << key);
+        out.write_all(&keys.to_be_bytes())?;
+        let state = match self.state {
+            CpuState::Running => [0, 0, NO_KEY],
+            CpuState::WaitingForKey { register, pressed } => [1, register, pressed.unwrap_or(NO_KEY)],
+        };
+        out.write_all(&state)?;
+
+        out.write_all(&self.ram)?;
+        for row in &self.vram {
+            for pixels in row.chunks(8) {
+                let byte = pixels.iter().fold(0u8, |byte, &pixel| byte << 1 | pixel & 1);
+                out.write_all(&[byte])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores a state written by `save_state`. The CPU is left untouched
+    /// when the state can't be read.
+    pub fn load_state(&mut self, input: &mut impl Read) -> io::Result<()> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a CHIP-8 save state"));
+        }
+        let version = read_u8(input)?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {version}")));
+        }
+
+        let program_counter = read_u16(input)? as usize;
+        let index_register = read_u16(input)?;
+        let mut var_registers = [0; 16];
+        input.read_exact(&mut var_registers)?;
+        let delay_timer = read_u8(input)?;
+        let sound_timer = read_u8(input)?;
+
+        let depth = read_u8(input)? as usize;
+        if depth > STACK_DEPTH {
+            return Err(invalid(&format!("{depth} nested subroutines")));
+        }
+        let mut stack = Stack::new(STACK_DEPTH);
+        for slot in 0..STACK_DEPTH {
+            let address = read_u16(input)? as usize;
+            if slot < depth {
+                // Can't overflow, the depth was checked
+                let _ = stack.push(address);
+            }
+        }
+
+        let key_bits = read_u16(input)?;
+        let keys = std::array::from_fn(|key| key_bits & 1 << key != 0);
+        let state = match [read_u8(input)?, read_u8(input)?, read_u8(input)?] {
+            [0, _, _] => CpuState::Running,
+            [1, register, NO_KEY] if register < 16 => CpuState::WaitingForKey { register, pressed: None },
+            [1, register, key] if register < 16 && (key as usize) < KEY_COUNT => CpuState::WaitingForKey {
+                register,
+                pressed: Some(key),
+            },
+            _ => return Err(invalid("bad key wait state")),
+        };
+
+        let mut ram = [0; RAM];
+        input.read_exact(&mut ram)?;
+        let mut vram = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        for row in &mut vram {
+            let mut bytes = [0; CHIP8_WIDTH / 8];
+            input.read_exact(&mut bytes)?;
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = bytes[x / 8] >> (7 - x % 8) & 1;
+            }
+        }
+
+        self.program_counter = program_counter;
+        self.index_register = index_register;
+        self.var_registers = var_registers;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.stack = stack;
+        self.keys = keys;
+        self.state = state;
+        self.ram = ram;
+        self.vram = vram;
+        Ok(())
+    }
+}
@@ -1,18 +1,31 @@
 #[derive(Debug)]
 pub struct Stack<T> {
     stack: Vec<T>,
+    capacity: usize,
 }
 
 impl<T> Stack<T> {
-    pub fn new() -> Self {
-        Stack { stack: Vec::new() }
+    pub fn new(capacity: usize) -> Self {
+        Stack {
+            stack: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.stack
     }
 
     pub fn pop(&mut self) -> Option<T> {
         self.stack.pop()
     }
 
-    pub fn push(&mut self, item: T) {
+    /// Hands the item back if the stack is full.
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.stack.len() >= self.capacity {
+            return Err(item);
+        }
         self.stack.push(item);
+        Ok(())
     }
 }
@@ -0,0 +1,640 @@
+use super::*;
+
+// 8x2 sprite with every pixel lit
+const BLOCK: [u8; 2] = [0xFF, 0xFF];
+const SPRITE_ADDR: usize = 0x300;
+
+fn clipping(clip_sprites: bool) -> Quirks {
+    Quirks {
+        clip_sprites,
+        ..Quirks::default()
+    }
+}
+
+/// Draws the block from V0/V1 with height `n`.
+fn draw(clip_sprites: bool, x: u8, y: u8, n: u16) -> CPU {
+    Program::with_quirks(clipping(clip_sprites), &[0xD010 | n])
+        .reg(0, x)
+        .reg(1, y)
+        .ram(SPRITE_ADDR, &BLOCK)
+        .index(SPRITE_ADDR as u16)
+        .run()
+}
+
+fn lit(cpu: &CPU) -> Vec<(usize, usize)> {
+    let mut pixels = Vec::new();
+    for (y, row) in cpu.vram.iter().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            if pixel == 1 {
+                pixels.push((x, y));
+            }
+        }
+    }
+    pixels
+}
+
+#[test]
+fn dxyn_clips_at_right_edge() {
+    let cpu = draw(true, 60, 0, 1);
+    assert_eq!(lit(&cpu), vec![(60, 0), (61, 0), (62, 0), (63, 0)]);
+}
+
+#[test]
+fn dxyn_wraps_at_right_edge() {
+    let cpu = draw(false, 60, 0, 1);
+    assert_eq!(lit(&cpu), vec![(0, 0), (1, 0), (2, 0), (3, 0), (60, 0), (61, 0), (62, 0), (63, 0)]);
+}
+
+#[test]
+fn xochip_preset_wraps_sprites() {
+    assert!(Quirks::preset(QuirkPreset::Chip8).clip_sprites);
+    assert!(Quirks::preset(QuirkPreset::Schip).clip_sprites);
+    assert_eq!("xo-chip".parse(), Ok(QuirkPreset::XoChip));
+
+    let cpu = Program::with_quirks(Quirks::preset(QuirkPreset::XoChip), &[0xD012])
+        .reg(0, 60)
+        .reg(1, 31)
+        .ram(SPRITE_ADDR, &BLOCK)
+        .index(SPRITE_ADDR as u16)
+        .run();
+    // The corner block wraps to all four corners
+    let columns = [0, 1, 2, 3, 60, 61, 62, 63];
+    let expected: Vec<_> = [0, 31].iter().flat_map(|&y| columns.map(|x| (x, y))).collect();
+    assert_eq!(lit(&cpu), expected);
+}
+
+#[test]
+fn dxyn_clips_at_bottom_edge() {
+    let cpu = draw(true, 0, 31, 2);
+    assert!(lit(&cpu).iter().all(|&(_, y)| y == 31));
+    assert_eq!(lit(&cpu).len(), 8);
+}
+
+#[test]
+fn dxyn_wraps_at_bottom_edge() {
+    let cpu = draw(false, 0, 31, 2);
+    let pixels = lit(&cpu);
+    assert_eq!(pixels.iter().filter(|&&(_, y)| y == 0).count(), 8);
+    assert_eq!(pixels.iter().filter(|&&(_, y)| y == 31).count(), 8);
+}
+
+#[test]
+fn dxyn_wraps_starting_position() {
+    for clip in [true, false] {
+        let cpu = draw(clip, 64 + 8, 32 + 4, 1);
+        assert_eq!(lit(&cpu), (8..16).map(|x| (x, 4)).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn dxyn_does_not_overflow_near_255() {
+    for clip in [true, false] {
+        let cpu = draw(clip, 255, 255, 2);
+        // 255 wraps to (63, 31)
+        assert!(lit(&cpu).contains(&(63, 31)));
+    }
+}
+
+#[test]
+fn dxyn_clipped_pixels_do_not_collide() {
+    for (clip, collision) in [(true, 0), (false, 1)] {
+        // Draw at (0, 0), then at (60, 0) where the right half wraps onto the first block
+        let cpu = Program::with_quirks(clipping(clip), &[0xD011, 0xD231])
+            .reg(0, 0)
+            .reg(1, 0)
+            .reg(2, 60)
+            .reg(3, 0)
+            .ram(SPRITE_ADDR, &BLOCK)
+            .index(SPRITE_ADDR as u16)
+            .run();
+        assert_eq!(cpu.var_registers[0xF], collision);
+    }
+}
+
+#[test]
+fn dxyn_reads_coordinates_from_vf_before_clearing_it() {
+    let cpu = Program::with_quirks(clipping(true), &[0xDF01])
+        .reg(0xF, 10)
+        .reg(0, 0)
+        .ram(SPRITE_ADDR, &BLOCK)
+        .index(SPRITE_ADDR as u16)
+        .run();
+    assert_eq!(lit(&cpu)[0], (10, 0));
+}
+
+#[test]
+fn bnnn_adds_v0_without_truncating() {
+    let cpu = Program::with_quirks(Quirks::preset(QuirkPreset::Chip8), &[0xB3F8])
+        .reg(0, 0x10)
+        .reg(3, 0x80)
+        .run();
+    assert_eq!(cpu.program_counter(), 0x408);
+}
+
+#[test]
+fn bnnn_wraps_to_12_bits() {
+    let cpu = Program::with_quirks(Quirks::preset(QuirkPreset::Chip8), &[0xBFF0])
+        .reg(0, 0x20)
+        .run();
+    assert_eq!(cpu.program_counter(), 0x010);
+}
+
+#[test]
+fn bxnn_adds_vx() {
+    let cpu = Program::with_quirks(Quirks::preset(QuirkPreset::Schip), &[0xB3F8])
+        .reg(0, 0x10)
+        .reg(3, 0x80)
+        .run();
+    assert_eq!(cpu.program_counter(), 0x478);
+}
+
+#[test]
+fn fx1e_leaves_vf_alone_by_default() {
+    let cpu = Program::new(&[0xF01E]).index(0xFFF).reg(0, 0x02).reg(0xF, 7).run();
+    assert_eq!(cpu.index_register(), 0x1001);
+    assert_eq!(cpu.var_registers[0xF], 7);
+}
+
+#[test]
+fn fx1e_overflow_sets_and_clears_vf() {
+    let quirks = Quirks {
+        index_overflow_sets_vf: true,
+        ..Quirks::default()
+    };
+    let cpu = Program::with_quirks(quirks, &[0xF01E, 0xF01E])
+        .index(0xFFE)
+        .reg(0, 0x01)
+        .reg(0xF, 1)
+        .run();
+    assert_eq!(cpu.index_register(), 0x1000);
+    assert_eq!(cpu.var_registers[0xF], 1);
+
+    let cpu = Program::with_quirks(quirks, &[0xF01E])
+        .index(0xFFE)
+        .reg(0, 0x01)
+        .reg(0xF, 1)
+        .run();
+    assert_eq!(cpu.index_register(), 0xFFF);
+    assert_eq!(cpu.var_registers[0xF], 0);
+}
+
+/// Assembles instructions at 0x200 and sets up the state they run against.
+struct Program {
+    cpu: CPU,
+    len: usize,
+}
+
+impl Program {
+    fn new(ops: &[u16]) -> Self {
+        Self::with_quirks(Quirks::default(), ops)
+    }
+
+    fn with_quirks(quirks: Quirks, ops: &[u16]) -> Self {
+        let mut cpu = CPU::with_quirks(quirks);
+        let bytes: Vec<u8> = ops.iter().flat_map(|op| op.to_be_bytes()).collect();
+        cpu.load_rom(&bytes);
+        Self { cpu, len: ops.len() }
+    }
+
+    fn reg(mut self, register: usize, value: u8) -> Self {
+        self.cpu.var_registers[register] = value;
+        self
+    }
+
+    fn index(mut self, index: u16) -> Self {
+        self.cpu.index_register = index;
+        self
+    }
+
+    fn ram(mut self, addr: usize, bytes: &[u8]) -> Self {
+        self.cpu.ram[addr..addr + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    fn key(mut self, key: usize) -> Self {
+        self.cpu.keys[key] = true;
+        self
+    }
+
+    /// Executes as many instructions as were assembled.
+    fn run(mut self) -> CPU {
+        for _ in 0..self.len {
+            self.cpu.cycle().unwrap();
+        }
+        self.cpu
+    }
+}
+
+#[test]
+fn op_00e0_clears_screen() {
+    let mut program = Program::new(&[0x00E0]);
+    program.cpu.vram[5][5] = 1;
+    let cpu = program.run();
+    assert!(lit(&cpu).is_empty());
+}
+
+#[test]
+fn op_1nnn_jumps() {
+    let cpu = Program::new(&[0x1ABC]).run();
+    assert_eq!(cpu.program_counter(), 0xABC);
+}
+
+#[test]
+fn op_2nnn_and_00ee_call_and_return() {
+    // 200: call 206, 202: V0 = 1, 204: halt, 206: return
+    let mut cpu = Program::new(&[0x2206, 0x6001, 0x1204, 0x00EE]).run();
+    assert_eq!(cpu.program_counter(), 0x204);
+    assert_eq!(cpu.var_registers[0], 1);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.program_counter(), 0x204);
+}
+
+#[test]
+fn op_3xnn_skips_when_equal() {
+    let cpu = Program::new(&[0x3042, 0x6101, 0x6202]).reg(0, 0x42).run();
+    assert_eq!(cpu.var_registers[1..3], [0, 2]);
+    let cpu = Program::new(&[0x3042, 0x6101, 0x6202]).reg(0, 0x41).run();
+    assert_eq!(cpu.var_registers[1..3], [1, 2]);
+}
+
+#[test]
+fn op_4xnn_skips_when_not_equal() {
+    let cpu = Program::new(&[0x4042, 0x6101]).reg(0, 0x41).run();
+    assert_eq!(cpu.var_registers[1], 0);
+    let cpu = Program::new(&[0x4042, 0x6101]).reg(0, 0x42).run();
+    assert_eq!(cpu.var_registers[1], 1);
+}
+
+#[test]
+fn op_5xy0_and_9xy0_compare_registers() {
+    let cpu = Program::new(&[0x5010, 0x6201]).reg(0, 7).reg(1, 7).run();
+    assert_eq!(cpu.var_registers[2], 0);
+    let cpu = Program::new(&[0x5010, 0x6201]).reg(0, 7).reg(1, 8).run();
+    assert_eq!(cpu.var_registers[2], 1);
+    let cpu = Program::new(&[0x9010, 0x6201]).reg(0, 7).reg(1, 8).run();
+    assert_eq!(cpu.var_registers[2], 0);
+    let cpu = Program::new(&[0x9010, 0x6201]).reg(0, 7).reg(1, 7).run();
+    assert_eq!(cpu.var_registers[2], 1);
+}
+
+#[test]
+fn op_6xnn_and_7xnn_set_and_add_without_carry() {
+    let cpu = Program::new(&[0x60FF, 0x7002]).reg(0xF, 5).run();
+    assert_eq!(cpu.var_registers[0], 1);
+    assert_eq!(cpu.var_registers[0xF], 5);
+}
+
+#[test]
+fn op_8xy0_to_8xy3_logic() {
+    let run = |op| Program::new(&[op]).reg(0, 0b1100).reg(1, 0b1010).run().var_registers[0];
+    assert_eq!(run(0x8010), 0b1010);
+    assert_eq!(run(0x8011), 0b1110);
+    assert_eq!(run(0x8012), 0b1000);
+    assert_eq!(run(0x8013), 0b0110);
+}
+
+#[test]
+fn op_8xy1_to_8xy3_reset_vf_on_chip8() {
+    for op in [0x8011, 0x8012, 0x8013] {
+        let cpu = Program::new(&[op]).reg(0xF, 1).run();
+        assert_eq!(cpu.var_registers[0xF], 0);
+
+        let cpu = Program::with_quirks(Quirks::preset(QuirkPreset::Schip), &[op]).reg(0xF, 1).run();
+        assert_eq!(cpu.var_registers[0xF], 1);
+    }
+}
+
+#[test]
+fn op_8xy4_sets_carry() {
+    let cpu = Program::new(&[0x8014]).reg(0, 200).reg(1, 100).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (44, 1));
+    let cpu = Program::new(&[0x8014]).reg(0, 20).reg(1, 10).reg(0xF, 1).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (30, 0));
+}
+
+#[test]
+fn op_8xy4_with_vf_operands() {
+    // The flag overwrites the sum when VF is the destination
+    let cpu = Program::new(&[0x8F04]).reg(0xF, 200).reg(0, 100).run();
+    assert_eq!(cpu.var_registers[0xF], 1);
+    // VF is read as an operand before the flag is set
+    let cpu = Program::new(&[0x80F4]).reg(0, 10).reg(0xF, 5).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (15, 0));
+}
+
+#[test]
+fn op_8xy5_sets_not_borrow() {
+    let cpu = Program::new(&[0x8015]).reg(0, 10).reg(1, 3).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (7, 1));
+    let cpu = Program::new(&[0x8015]).reg(0, 3).reg(1, 10).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (249, 0));
+    // Equal operands don't borrow
+    let cpu = Program::new(&[0x8015]).reg(0, 3).reg(1, 3).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (0, 1));
+}
+
+#[test]
+fn op_8xy5_with_vf_operands() {
+    let cpu = Program::new(&[0x8F05]).reg(0xF, 3).reg(0, 10).run();
+    assert_eq!(cpu.var_registers[0xF], 0);
+    let cpu = Program::new(&[0x80F5]).reg(0, 10).reg(0xF, 3).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (7, 1));
+}
+
+#[test]
+fn op_8xy7_sets_not_borrow() {
+    let cpu = Program::new(&[0x8017]).reg(0, 3).reg(1, 10).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (7, 1));
+    let cpu = Program::new(&[0x8017]).reg(0, 10).reg(1, 3).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (249, 0));
+}
+
+#[test]
+fn op_8xy7_with_vf_operands() {
+    let cpu = Program::new(&[0x8F07]).reg(0xF, 3).reg(0, 10).run();
+    assert_eq!(cpu.var_registers[0xF], 1);
+    let cpu = Program::new(&[0x80F7]).reg(0, 10).reg(0xF, 3).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (249, 0));
+}
+
+#[test]
+fn op_8xy6_and_8xye_shift_vy_on_chip8() {
+    let cpu = Program::new(&[0x8016]).reg(0, 0xFF).reg(1, 0b0000_0011).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (0b0000_0001, 1));
+    let cpu = Program::new(&[0x801E]).reg(0, 0).reg(1, 0b1000_0001).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (0b0000_0010, 1));
+}
+
+#[test]
+fn op_8xy6_and_8xye_shift_vx_on_schip() {
+    let schip = Quirks::preset(QuirkPreset::Schip);
+    let cpu = Program::with_quirks(schip, &[0x8016]).reg(0, 0b0000_0100).reg(1, 0xFF).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (0b0000_0010, 0));
+    let cpu = Program::with_quirks(schip, &[0x801E]).reg(0, 0b0100_0000).reg(1, 0xFF).run();
+    assert_eq!((cpu.var_registers[0], cpu.var_registers[0xF]), (0b1000_0000, 0));
+}
+
+#[test]
+fn op_8xy6_and_8xye_with_vf_destination() {
+    let cpu = Program::new(&[0x8F06]).reg(0xF, 0b10).run();
+    assert_eq!(cpu.var_registers[0xF], 0);
+    let cpu = Program::new(&[0x8F0E]).reg(0, 0b1000_0000).run();
+    assert_eq!(cpu.var_registers[0xF], 1);
+}
+
+#[test]
+fn op_annn_sets_index() {
+    let cpu = Program::new(&[0xA123]).run();
+    assert_eq!(cpu.index_register(), 0x123);
+}
+
+#[test]
+fn op_cxnn_masks_random_value() {
+    let cpu = Program::new(&[0xC000]).reg(0, 0xFF).run();
+    assert_eq!(cpu.var_registers[0], 0);
+    for _ in 0..32 {
+        let cpu = Program::new(&[0xC00F]).run();
+        assert_eq!(cpu.var_registers[0] & 0xF0, 0);
+    }
+}
+
+#[test]
+fn op_dxyn_sets_vf_on_collision_and_erases() {
+    let program = [0xD012, 0xD012];
+    let cpu = Program::new(&program[..1]).index(SPRITE_ADDR as u16).ram(SPRITE_ADDR, &BLOCK).run();
+    assert_eq!(cpu.var_registers[0xF], 0);
+    assert_eq!(lit(&cpu).len(), 16);
+
+    let cpu = Program::new(&program).index(SPRITE_ADDR as u16).ram(SPRITE_ADDR, &BLOCK).run();
+    assert_eq!(cpu.var_registers[0xF], 1);
+    assert!(lit(&cpu).is_empty());
+}
+
+#[test]
+fn op_dxyn_no_collision_on_disjoint_sprites() {
+    // Second sprite drawn 8 pixels to the right
+    let cpu = Program::new(&[0xD012, 0x7008, 0xD012])
+        .index(SPRITE_ADDR as u16)
+        .ram(SPRITE_ADDR, &BLOCK)
+        .run();
+    assert_eq!(cpu.var_registers[0xF], 0);
+    assert_eq!(lit(&cpu).len(), 32);
+}
+
+#[test]
+fn op_ex9e_and_exa1_test_keys() {
+    let cpu = Program::new(&[0xE09E, 0x6101]).reg(0, 0xA).key(0xA).run();
+    assert_eq!(cpu.var_registers[1], 0);
+    let cpu = Program::new(&[0xE09E, 0x6101]).reg(0, 0xA).run();
+    assert_eq!(cpu.var_registers[1], 1);
+    let cpu = Program::new(&[0xE0A1, 0x6101]).reg(0, 0xA).run();
+    assert_eq!(cpu.var_registers[1], 0);
+    let cpu = Program::new(&[0xE0A1, 0x6101]).reg(0, 0xA).key(0xA).run();
+    assert_eq!(cpu.var_registers[1], 1);
+}
+
+#[test]
+fn op_fx07_fx15_fx18_timers() {
+    let cpu = Program::new(&[0xF015, 0xF118, 0xF207]).reg(0, 30).reg(1, 40).run();
+    assert_eq!(cpu.delay_timer, 30);
+    assert_eq!(cpu.sound_timer, 40);
+    assert_eq!(cpu.var_registers[2], 30);
+}
+
+#[test]
+fn op_fx0a_waits_for_release() {
+    let mut cpu = Program::new(&[0xF30A]).run();
+    assert_eq!(cpu.program_counter(), 0x200);
+
+    cpu.keypress(5, true);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.state(), CpuState::WaitingForKey { register: 3, pressed: Some(5) });
+
+    cpu.keypress(5, false);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.state(), CpuState::Running);
+    assert_eq!(cpu.var_registers[3], 5);
+    assert_eq!(cpu.program_counter(), 0x202);
+}
+
+#[test]
+fn op_fx0a_continues_on_press_with_quirk() {
+    let quirks = Quirks {
+        key_wait_on_press: true,
+        ..Quirks::default()
+    };
+    let mut cpu = Program::with_quirks(quirks, &[0xF30A]).run();
+    assert_eq!(cpu.program_counter(), 0x200);
+
+    cpu.keypress(5, true);
+    cpu.cycle().unwrap();
+    assert_eq!(cpu.state(), CpuState::Running);
+    assert_eq!(cpu.var_registers[3], 5);
+    assert_eq!(cpu.program_counter(), 0x202);
+}
+
+#[test]
+fn op_fx1e_adds_to_index() {
+    let cpu = Program::new(&[0xF01E]).index(0x100).reg(0, 0x22).run();
+    assert_eq!(cpu.index_register(), 0x122);
+}
+
+#[test]
+fn op_fx29_points_at_font_sprite() {
+    let cpu = Program::new(&[0xF029]).reg(0, 0xA).run();
+    assert_eq!(cpu.index_register(), 0xA * SPRITE_WIDTH as u16);
+    assert_eq!(cpu.ram[cpu.index_register() as usize..][..5], FONT[50..55]);
+}
+
+#[test]
+fn op_fx33_stores_bcd() {
+    for (value, digits) in [(0, [0, 0, 0]), (9, [0, 0, 9]), (100, [1, 0, 0]), (255, [2, 5, 5])] {
+        let cpu = Program::new(&[0xF033]).reg(0, value).index(0x300).run();
+        assert_eq!(cpu.ram[0x300..0x303], digits);
+    }
+}
+
+#[test]
+fn op_fx55_stores_v0_through_vx_inclusive() {
+    let cpu = Program::new(&[0xF255])
+        .reg(0, 1)
+        .reg(1, 2)
+        .reg(2, 3)
+        .reg(3, 4)
+        .index(0x300)
+        .run();
+    assert_eq!(cpu.ram[0x300..0x304], [1, 2, 3, 0]);
+    assert_eq!(cpu.index_register(), 0x303);
+
+    let cpu = Program::with_quirks(Quirks::preset(QuirkPreset::Schip), &[0xF255]).index(0x300).run();
+    assert_eq!(cpu.index_register(), 0x300);
+}
+
+#[test]
+fn op_fx55_stores_all_registers_up_to_end_of_ram() {
+    let mut program = Program::new(&[0xFF55]).index(0xFF0);
+    for register in 0..16 {
+        program = program.reg(register, register as u8 + 1);
+    }
+    let cpu = program.run();
+    assert_eq!(cpu.ram[0xFF0..], std::array::from_fn::<u8, 16, _>(|i| i as u8 + 1));
+}
+
+#[test]
+fn op_fx65_loads_v0_through_vx_inclusive() {
+    let cpu = Program::new(&[0xF265]).ram(0x300, &[1, 2, 3, 4]).index(0x300).run();
+    assert_eq!(cpu.var_registers[..4], [1, 2, 3, 0]);
+    assert_eq!(cpu.index_register(), 0x303);
+
+    let cpu = Program::with_quirks(Quirks::preset(QuirkPreset::Schip), &[0xF265]).index(0x300).run();
+    assert_eq!(cpu.index_register(), 0x300);
+}
+
+#[test]
+fn op_fx65_loads_all_registers_from_end_of_ram() {
+    let bytes: [u8; 16] = std::array::from_fn(|i| 0xF0 | i as u8);
+    let cpu = Program::new(&[0xFF65]).ram(0xFF0, &bytes).index(0xFF0).run();
+    assert_eq!(cpu.var_registers, bytes);
+}
+
+#[test]
+fn return_with_empty_stack_is_an_error() {
+    let mut cpu = Program::new(&[0x00EE]).cpu;
+    assert_eq!(cpu.cycle(), Err(CpuError::StackUnderflow { pc: 0x200 }));
+}
+
+#[test]
+fn unknown_opcodes_are_an_error() {
+    for op in [0x5121, 0x8008, 0x9121, 0xE0FF, 0xF0FF] {
+        let mut cpu = Program::new(&[op]).cpu;
+        assert_eq!(cpu.cycle(), Err(CpuError::UnknownOpcode { pc: 0x200, opcode: op }), "{op:04X}");
+        assert_eq!(cpu.program_counter(), 0x200);
+    }
+}
+
+#[test]
+fn machine_code_calls_are_skipped() {
+    let cpu = Program::new(&[0x0123, 0x6001]).run();
+    assert_eq!(cpu.var_registers[0], 1);
+}
+
+#[test]
+fn faults_leave_pc_on_the_faulting_instruction() {
+    let mut cpu = Program::new(&[0x00EE]).cpu;
+    assert!(cpu.cycle().is_err());
+    assert_eq!(cpu.program_counter(), 0x200);
+
+    let mut cpu = Program::new(&[0xF165]).index(0xFFF).cpu;
+    assert!(cpu.cycle().is_err());
+    assert_eq!(cpu.program_counter(), 0x200);
+    // Retrying hits the same fault
+    assert_eq!(cpu.cycle(), Err(CpuError::MemoryOutOfBounds { pc: 0x200, address: RAM }));
+}
+
+#[test]
+fn unbounded_recursion_is_an_error() {
+    let mut cpu = Program::new(&[0x2200]).cpu;
+    for _ in 0..STACK_DEPTH {
+        cpu.cycle().unwrap();
+    }
+    assert_eq!(cpu.cycle(), Err(CpuError::StackOverflow { pc: 0x200 }));
+}
+
+#[test]
+fn running_off_the_end_of_ram_is_an_error() {
+    let mut cpu = Program::new(&[0x1FFF]).run();
+    assert_eq!(cpu.cycle(), Err(CpuError::PcOutOfRange { pc: 0xFFF }));
+}
+
+#[test]
+fn memory_access_past_ram_is_an_error() {
+    for op in [0xF033, 0xF255, 0xF265, 0xD005] {
+        let mut cpu = Program::new(&[op]).index(0xFFE).cpu;
+        assert_eq!(
+            cpu.cycle(),
+            Err(CpuError::MemoryOutOfBounds { pc: 0x200, address: RAM }),
+            "{op:04X}"
+        );
+    }
+}
+
+#[test]
+fn key_and_font_lookups_use_low_nibble() {
+    let cpu = Program::new(&[0xE09E, 0x6101]).reg(0, 0xFA).key(0xA).run();
+    assert_eq!(cpu.var_registers[1], 0);
+    let cpu = Program::new(&[0xF029]).reg(0, 0xFA).run();
+    assert_eq!(cpu.index_register(), 0xA * SPRITE_WIDTH as u16);
+}
+
+#[test]
+fn save_state_round_trips() {
+    // Call a subroutine, draw the 0 glyph and wait for a key
+    let mut cpu = Program::new(&[0x2206, 0x0000, 0x0000, 0xD015, 0xF30A]).reg(7, 0x42).key(0xC).run();
+    cpu.set_delay_timer(9);
+    let mut state = Vec::new();
+    cpu.save_state(&mut state).unwrap();
+
+    let mut loaded = CPU::with_quirks(Quirks::default());
+    loaded.load_state(&mut state.as_slice()).unwrap();
+    assert_eq!(loaded.program_counter(), cpu.program_counter());
+    assert_eq!(loaded.stack(), &[0x202]);
+    assert_eq!(loaded.registers(), cpu.registers());
+    assert_eq!(loaded.delay_timer(), 9);
+    assert_eq!(loaded.keys(), cpu.keys());
+    assert_eq!(loaded.state(), cpu.state());
+    assert_eq!(loaded.memory(), cpu.memory());
+    assert_eq!(lit(&loaded), lit(&cpu));
+    assert!(!lit(&loaded).is_empty());
+}
+
+#[test]
+fn bad_save_state_leaves_cpu_untouched() {
+    let mut state = Vec::new();
+    Program::new(&[0x6001]).run().save_state(&mut state).unwrap();
+    state.truncate(state.len() - 1);
+
+    let mut cpu = Program::new(&[0x6002]).run();
+    assert!(cpu.load_state(&mut state.as_slice()).is_err());
+    assert!(cpu.load_state(&mut &b"ROM!"[..]).is_err());
+    assert_eq!(cpu.registers()[0], 2);
+}
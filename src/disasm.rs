@@ -0,0 +1,201 @@
+//! Opcode decoding into mnemonics, using Cowgod's names.
+
+use std::fmt;
+
+/// A decoded instruction. Registers are indices 0x0-0xF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0NNN, machine language subroutine, ignored by the interpreter
+    Sys(u16),
+    Cls,
+    Ret,
+    Jp(u16),
+    Call(u16),
+    SeByte(u8, u8),
+    SneByte(u8, u8),
+    SeReg(u8, u8),
+    LdByte(u8, u8),
+    AddByte(u8, u8),
+    LdReg(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddReg(u8, u8),
+    Sub(u8, u8),
+    Shr(u8, u8),
+    Subn(u8, u8),
+    Shl(u8, u8),
+    SneReg(u8, u8),
+    LdI(u16),
+    /// BNNN, V0 + NNN, or VX + NN with the SUPER-CHIP quirk
+    JpOffset(u16),
+    Rnd(u8, u8),
+    Drw(u8, u8, u8),
+    Skp(u8),
+    Sknp(u8),
+    LdFromDt(u8),
+    LdKey(u8),
+    LdDt(u8),
+    LdSt(u8),
+    AddI(u8),
+    LdFont(u8),
+    LdBcd(u8),
+    Store(u8),
+    Load(u8),
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Self {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        match (opcode >> 12, n) {
+            (0x0, _) => match opcode {
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Ret,
+                _ => Instruction::Sys(nnn),
+            },
+            (0x1, _) => Instruction::Jp(nnn),
+            (0x2, _) => Instruction::Call(nnn),
+            (0x3, _) => Instruction::SeByte(x, nn),
+            (0x4, _) => Instruction::SneByte(x, nn),
+            (0x5, 0x0) => Instruction::SeReg(x, y),
+            (0x6, _) => Instruction::LdByte(x, nn),
+            (0x7, _) => Instruction::AddByte(x, nn),
+            (0x8, 0x0) => Instruction::LdReg(x, y),
+            (0x8, 0x1) => Instruction::Or(x, y),
+            (0x8, 0x2) => Instruction::And(x, y),
+            (0x8, 0x3) => Instruction::Xor(x, y),
+            (0x8, 0x4) => Instruction::AddReg(x, y),
+            (0x8, 0x5) => Instruction::Sub(x, y),
+            (0x8, 0x6) => Instruction::Shr(x, y),
+            (0x8, 0x7) => Instruction::Subn(x, y),
+            (0x8, 0xE) => Instruction::Shl(x, y),
+            (0x9, 0x0) => Instruction::SneReg(x, y),
+            (0xA, _) => Instruction::LdI(nnn),
+            (0xB, _) => Instruction::JpOffset(nnn),
+            (0xC, _) => Instruction::Rnd(x, nn),
+            (0xD, _) => Instruction::Drw(x, y, n),
+            (0xE, _) if nn == 0x9E => Instruction::Skp(x),
+            (0xE, _) if nn == 0xA1 => Instruction::Sknp(x),
+            (0xF, _) => match nn {
+                0x07 => Instruction::LdFromDt(x),
+                0x0A => Instruction::LdKey(x),
+                0x15 => Instruction::LdDt(x),
+                0x18 => Instruction::LdSt(x),
+                0x1E => Instruction::AddI(x),
+                0x29 => Instruction::LdFont(x),
+                0x33 => Instruction::LdBcd(x),
+                0x55 => Instruction::Store(x),
+                0x65 => Instruction::Load(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Sys(nnn) => write!(f, "SYS {nnn:#05X}"),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jp(nnn) => write!(f, "JP {nnn:#05X}"),
+            Instruction::Call(nnn) => write!(f, "CALL {nnn:#05X}"),
+            Instruction::SeByte(x, nn) => write!(f, "SE V{x:X}, {nn:#04X}"),
+            Instruction::SneByte(x, nn) => write!(f, "SNE V{x:X}, {nn:#04X}"),
+            Instruction::SeReg(x, y) => write!(f, "SE V{x:X}, V{y:X}"),
+            Instruction::LdByte(x, nn) => write!(f, "LD V{x:X}, {nn:#04X}"),
+            Instruction::AddByte(x, nn) => write!(f, "ADD V{x:X}, {nn:#04X}"),
+            Instruction::LdReg(x, y) => write!(f, "LD V{x:X}, V{y:X}"),
+            Instruction::Or(x, y) => write!(f, "OR V{x:X}, V{y:X}"),
+            Instruction::And(x, y) => write!(f, "AND V{x:X}, V{y:X}"),
+            Instruction::Xor(x, y) => write!(f, "XOR V{x:X}, V{y:X}"),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{x:X}, V{y:X}"),
+            Instruction::Sub(x, y) => write!(f, "SUB V{x:X}, V{y:X}"),
+            Instruction::Shr(x, y) => write!(f, "SHR V{x:X}, V{y:X}"),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Instruction::Shl(x, y) => write!(f, "SHL V{x:X}, V{y:X}"),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{x:X}, V{y:X}"),
+            Instruction::LdI(nnn) => write!(f, "LD I, {nnn:#05X}"),
+            Instruction::JpOffset(nnn) => write!(f, "JP V0, {nnn:#05X}"),
+            Instruction::Rnd(x, nn) => write!(f, "RND V{x:X}, {nn:#04X}"),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{x:X}, V{y:X}, {n}"),
+            Instruction::Skp(x) => write!(f, "SKP V{x:X}"),
+            Instruction::Sknp(x) => write!(f, "SKNP V{x:X}"),
+            Instruction::LdFromDt(x) => write!(f, "LD V{x:X}, DT"),
+            Instruction::LdKey(x) => write!(f, "LD V{x:X}, K"),
+            Instruction::LdDt(x) => write!(f, "LD DT, V{x:X}"),
+            Instruction::LdSt(x) => write!(f, "LD ST, V{x:X}"),
+            Instruction::AddI(x) => write!(f, "ADD I, V{x:X}"),
+            Instruction::LdFont(x) => write!(f, "LD F, V{x:X}"),
+            Instruction::LdBcd(x) => write!(f, "LD B, V{x:X}"),
+            Instruction::Store(x) => write!(f, "LD [I], V{x:X}"),
+            Instruction::Load(x) => write!(f, "LD V{x:X}, [I]"),
+            Instruction::Unknown(opcode) => write!(f, "DW {opcode:#06X}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_operands() {
+        assert_eq!(Instruction::decode(0x00E0), Instruction::Cls);
+        assert_eq!(Instruction::decode(0x00EE), Instruction::Ret);
+        assert_eq!(Instruction::decode(0x0123), Instruction::Sys(0x123));
+        assert_eq!(Instruction::decode(0x2ABC), Instruction::Call(0xABC));
+        assert_eq!(Instruction::decode(0x3A42), Instruction::SeByte(0xA, 0x42));
+        assert_eq!(Instruction::decode(0x8AB4), Instruction::AddReg(0xA, 0xB));
+        assert_eq!(Instruction::decode(0x8ABE), Instruction::Shl(0xA, 0xB));
+        assert_eq!(Instruction::decode(0xD12F), Instruction::Drw(1, 2, 0xF));
+        assert_eq!(Instruction::decode(0xE59E), Instruction::Skp(5));
+        assert_eq!(Instruction::decode(0xF765), Instruction::Load(7));
+    }
+
+    #[test]
+    fn unknown_opcodes_are_kept() {
+        for opcode in [0x5121, 0x8128, 0x9121, 0xE1FF, 0xF1FF] {
+            assert_eq!(Instruction::decode(opcode), Instruction::Unknown(opcode), "{opcode:04X}");
+        }
+    }
+
+    #[test]
+    fn every_opcode_has_a_mnemonic() {
+        for opcode in 0..=u16::MAX {
+            assert!(!Instruction::decode(opcode).to_string().is_empty());
+        }
+    }
+
+    #[test]
+    fn formats_cowgod_mnemonics() {
+        let cases = [
+            (0x0123, "SYS 0x123"),
+            (0x00E0, "CLS"),
+            (0x1208, "JP 0x208"),
+            (0x3A04, "SE VA, 0x04"),
+            (0x5120, "SE V1, V2"),
+            (0x7FFF, "ADD VF, 0xFF"),
+            (0x8127, "SUBN V1, V2"),
+            (0xA300, "LD I, 0x300"),
+            (0xB200, "JP V0, 0x200"),
+            (0xC10F, "RND V1, 0x0F"),
+            (0xD125, "DRW V1, V2, 5"),
+            (0xF00A, "LD V0, K"),
+            (0xF233, "LD B, V2"),
+            (0xF255, "LD [I], V2"),
+            (0xF265, "LD V2, [I]"),
+            (0xFFFF, "DW 0xFFFF"),
+        ];
+        for (opcode, mnemonic) in cases {
+            assert_eq!(Instruction::decode(opcode).to_string(), mnemonic, "{opcode:04X}");
+        }
+    }
+}
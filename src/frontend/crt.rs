@@ -0,0 +1,234 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use super::{display::Frame, palette::Rgb};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Effect {
+    #[default]
+    None,
+    Scanlines,
+    Grid,
+    // Scanlines, bloom and a curved screen
+    Crt,
+}
+
+impl Effect {
+    pub const NAMES: [&'static str; 4] = ["none", "scanlines", "grid", "crt"];
+
+    pub fn next(self) -> Self {
+        match self {
+            Effect::None => Effect::Scanlines,
+            Effect::Scanlines => Effect::Grid,
+            Effect::Grid => Effect::Crt,
+            Effect::Crt => Effect::None,
+        }
+    }
+
+    fn scanlines(self) -> bool {
+        matches!(self, Effect::Scanlines | Effect::Crt)
+    }
+
+    fn grid(self) -> bool {
+        self == Effect::Grid
+    }
+
+    fn bloom(self) -> bool {
+        self == Effect::Crt
+    }
+
+    fn curvature(self) -> bool {
+        self == Effect::Crt
+    }
+}
+
+impl FromStr for Effect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Effect::None),
+            "scanlines" => Ok(Effect::Scanlines),
+            "grid" => Ok(Effect::Grid),
+            "crt" => Ok(Effect::Crt),
+            _ => Err(format!("unknown effect '{s}', expected one of: {}", Self::NAMES.join(", "))),
+        }
+    }
+}
+
+impl TryFrom<String> for Effect {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+const SCANLINE_BRIGHTNESS: f32 = 0.55;
+const GRID_BRIGHTNESS: f32 = 0.6;
+const BLOOM_STRENGTH: f32 = 0.35;
+const CURVATURE: f32 = 0.06;
+
+// Where an output pixel samples the frame, and how much it is darkened
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    source: Option<usize>,
+    brightness: f32,
+}
+
+/// Upscales a `Frame` on the CPU and applies the selected `Effect`.
+///
+/// The sample positions only depend on the effect and the frame size, so they
+/// are computed once and each frame is just a lookup per output pixel.
+#[derive(Debug)]
+pub struct CrtFilter {
+    effect: Effect,
+    scale: usize,
+    width: usize,
+    height: usize,
+    samples: Vec<Sample>,
+    bloom: Vec<[f32; 3]>,
+    output: Vec<u8>,
+}
+
+impl CrtFilter {
+    pub fn new(effect: Effect, scale: usize) -> Self {
+        Self {
+            effect,
+            scale: scale.max(1),
+            width: 0,
+            height: 0,
+            samples: Vec::new(),
+            bloom: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    pub fn effect(&self) -> Effect {
+        self.effect
+    }
+
+    pub fn set_effect(&mut self, effect: Effect) {
+        self.effect = effect;
+        // Recompute the sample map on the next frame
+        self.samples.clear();
+    }
+
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: usize) {
+        self.scale = scale.max(1);
+        self.samples.clear();
+    }
+
+    /// Size of the processed image in pixels.
+    pub fn output_size(&self, frame: &Frame) -> (usize, usize) {
+        (frame.width * self.scale, frame.height * self.scale)
+    }
+
+    /// Returns the processed image as tightly packed RGB24 rows.
+    pub fn process(&mut self, frame: &Frame) -> &[u8] {
+        let (width, height) = self.output_size(frame);
+        if self.samples.is_empty() || width != self.width || height != self.height {
+            self.width = width;
+            self.height = height;
+            self.build_samples(frame.width, frame.height);
+            self.output = vec![0; width * height * 3];
+        }
+
+        if self.effect.bloom() {
+            self.blur(frame);
+        }
+
+        for (sample, out) in self.samples.iter().zip(self.output.chunks_exact_mut(3)) {
+            let Some(source) = sample.source else {
+                out.fill(0);
+                continue;
+            };
+
+            let Rgb(r, g, b) = frame.pixels[source];
+            let mut color = [r as f32, g as f32, b as f32];
+            if self.effect.bloom() {
+                for (channel, glow) in color.iter_mut().zip(self.bloom[source]) {
+                    *channel += glow * BLOOM_STRENGTH;
+                }
+            }
+
+            for (out, channel) in out.iter_mut().zip(color) {
+                *out = (channel * sample.brightness).min(255.0) as u8;
+            }
+        }
+
+        &self.output
+    }
+
+    fn build_samples(&mut self, frame_width: usize, frame_height: usize) {
+        let scale = self.scale as f32;
+        let (width, height) = (self.width as f32, self.height as f32);
+        let effect = self.effect;
+
+        self.samples = (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (mut fx, mut fy) = (x as f32 + 0.5, y as f32 + 0.5);
+                let mut brightness = 1.0;
+
+                if effect.curvature() {
+                    // Barrel distortion in normalised [-1, 1] screen space
+                    let u = fx / width * 2.0 - 1.0;
+                    let v = fy / height * 2.0 - 1.0;
+                    let cu = u * (1.0 + CURVATURE * v * v);
+                    let cv = v * (1.0 + CURVATURE * u * u);
+                    if cu.abs() > 1.0 || cv.abs() > 1.0 {
+                        return Sample { source: None, brightness };
+                    }
+                    fx = (cu + 1.0) / 2.0 * width;
+                    fy = (cv + 1.0) / 2.0 * height;
+                    // Darken the corners slightly
+                    brightness *= 1.0 - 0.25 * (cu * cu * cv * cv);
+                }
+
+                let cell_x = ((fx / scale) as usize).min(frame_width - 1);
+                let cell_y = ((fy / scale) as usize).min(frame_height - 1);
+                let (sub_x, sub_y) = (fx % scale / scale, fy % scale / scale);
+
+                if effect.scanlines() && self.scale > 1 && sub_y >= 0.5 {
+                    brightness *= SCANLINE_BRIGHTNESS;
+                }
+                if effect.grid() && self.scale > 2 && (sub_x >= 1.0 - 1.0 / scale || sub_y >= 1.0 - 1.0 / scale) {
+                    brightness *= GRID_BRIGHTNESS;
+                }
+
+                Sample {
+                    source: Some(cell_y * frame_width + cell_x),
+                    brightness,
+                }
+            })
+            .collect();
+    }
+
+    // 3x3 box blur of the frame, added on top of lit pixels and their neighbours
+    fn blur(&mut self, frame: &Frame) {
+        let (width, height) = (frame.width, frame.height);
+        self.bloom.resize(frame.pixels.len(), [0.0; 3]);
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 3];
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        let Rgb(r, g, b) = frame.pixels[ny * width + nx];
+                        sum[0] += r as f32;
+                        sum[1] += g as f32;
+                        sum[2] += b as f32;
+                    }
+                }
+                self.bloom[y * width + x] = sum.map(|channel| channel / 9.0);
+            }
+        }
+    }
+}
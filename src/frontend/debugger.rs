@@ -0,0 +1,367 @@
+//! The in-window debugger, drawn with Dear ImGui over the game.
+//!
+//! Opening it doesn't stop the game, pausing does. While paused the game
+//! only advances by the step buttons, one instruction or one frame at a time.
+
+use imgui::{Condition, ImColor32, ListClipper, ProgressBar, StyleColor, Ui};
+
+use chip8::cpu::{CpuError, CHIP8_HEIGHT, CHIP8_WIDTH, RAM};
+use chip8::disasm::Instruction;
+use chip8::machine::Machine;
+
+/// Instructions listed before and after the PC.
+const DISASSEMBLY_BEFORE: usize = 8;
+const DISASSEMBLY_AFTER: usize = 16;
+const MEMORY_COLUMNS: usize = 16;
+
+/// Keys in the order they sit on the COSMAC VIP keypad.
+const KEYPAD: [[usize; 4]; 4] = [[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]];
+
+const HIGHLIGHT: [f32; 4] = [1.0, 0.85, 0.3, 1.0];
+const INDEX_COLOR: [f32; 4] = [0.4, 0.9, 0.5, 1.0];
+const ERROR_COLOR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Instruction,
+    Frame,
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    visible: bool,
+    paused: bool,
+    step: Option<Step>,
+    fault: Option<CpuError>,
+    // Byte selected in the memory editor, and whether to scroll to it
+    selected: usize,
+    scroll_to_selected: bool,
+    // Keypad key held down with the mouse
+    clicked_key: Option<usize>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Hiding the debugger also resumes the game.
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        if !self.visible {
+            self.paused = false;
+            self.fault = None;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses on the faulting instruction, so it can be inspected. The CPU
+    /// stays on it, stepping retries it once the cause has been edited away.
+    pub fn fault(&mut self, error: CpuError) {
+        self.paused = true;
+        self.fault = Some(error);
+    }
+
+    /// Runs the step asked for since the last call, if any.
+    pub fn run_step(&mut self, machine: &mut Machine) -> Result<(), CpuError> {
+        let result = match self.step.take() {
+            Some(Step::Instruction) => machine.cpu_mut().cycle().map(|_| ()),
+            Some(Step::Frame) => machine.run_frame().map(|_| ()),
+            None => return Ok(()),
+        };
+        // Got past the fault, it no longer describes where the CPU is
+        if result.is_ok() {
+            self.fault = None;
+        }
+        result
+    }
+
+    pub fn draw(&mut self, ui: &Ui, machine: &mut Machine) {
+        self.controls(ui);
+        self.registers(ui, machine);
+        self.stack(ui, machine);
+        self.timers(ui, machine);
+        self.disassembly(ui, machine);
+        self.memory(ui, machine);
+        self.keypad(ui, machine);
+        self.framebuffer(ui, machine);
+    }
+
+    fn controls(&mut self, ui: &Ui) {
+        ui.window("Debugger")
+            .position([10.0, 10.0], Condition::FirstUseEver)
+            .always_auto_resize(true)
+            .build(|| {
+                if ui.button(if self.paused { "Run" } else { "Pause" }) {
+                    self.paused = !self.paused;
+                    self.fault = None;
+                }
+                ui.disabled(!self.paused, || {
+                    ui.same_line();
+                    if ui.button("Step") {
+                        self.step = Some(Step::Instruction);
+                    }
+                    ui.same_line();
+                    if ui.button("Frame") {
+                        self.step = Some(Step::Frame);
+                    }
+                });
+                if let Some(fault) = &self.fault {
+                    ui.text_colored(ERROR_COLOR, fault.to_string());
+                }
+                ui.text_disabled("F1 hides the debugger");
+            });
+    }
+
+    fn registers(&mut self, ui: &Ui, machine: &mut Machine) {
+        ui.window("Registers")
+            .position([10.0, 110.0], Condition::FirstUseEver)
+            .always_auto_resize(true)
+            .build(|| {
+                let cpu = machine.cpu_mut();
+                let _width = ui.push_item_width(ui.calc_text_size("000")[0] + 16.0);
+                for (register, value) in cpu.registers_mut().iter_mut().enumerate() {
+                    if register % 4 != 0 {
+                        ui.same_line();
+                    }
+                    hex_input(ui, &format!("V{register:X}"), value, "%02X");
+                }
+
+                let mut index = cpu.index_register();
+                if hex_input(ui, "I", &mut index, "%03X") {
+                    cpu.set_index_register(index);
+                }
+                ui.same_line();
+                let mut pc = cpu.program_counter() as u16;
+                if hex_input(ui, "PC", &mut pc, "%03X") && (pc as usize) < RAM {
+                    cpu.set_program_counter(pc as usize);
+                }
+            });
+    }
+
+    fn stack(&mut self, ui: &Ui, machine: &Machine) {
+        ui.window("Stack")
+            .position([10.0, 250.0], Condition::FirstUseEver)
+            .size([140.0, 200.0], Condition::FirstUseEver)
+            .build(|| {
+                let stack = machine.cpu().stack();
+                if stack.is_empty() {
+                    ui.text_disabled("empty");
+                }
+                // Innermost call first
+                for (depth, address) in stack.iter().enumerate().rev() {
+                    ui.text(format!("{depth:>2}  {address:03X}"));
+                }
+            });
+    }
+
+    fn timers(&mut self, ui: &Ui, machine: &mut Machine) {
+        ui.window("Timers")
+            .position([10.0, 460.0], Condition::FirstUseEver)
+            .always_auto_resize(true)
+            .build(|| {
+                let cpu = machine.cpu_mut();
+                let _width = ui.push_item_width(ui.calc_text_size("00")[0] + 16.0);
+
+                let mut delay = cpu.delay_timer();
+                if hex_input(ui, "DT", &mut delay, "%02X") {
+                    cpu.set_delay_timer(delay);
+                }
+                ui.same_line();
+                ProgressBar::new(delay as f32 / u8::MAX as f32).size([120.0, 0.0]).overlay_text("").build(ui);
+
+                let mut sound = cpu.sound_timer();
+                if hex_input(ui, "ST", &mut sound, "%02X") {
+                    cpu.set_sound_timer(sound);
+                }
+                ui.same_line();
+                ProgressBar::new(sound as f32 / u8::MAX as f32)
+                    .size([120.0, 0.0])
+                    .overlay_text(if sound > 0 { "beep" } else { "" })
+                    .build(ui);
+            });
+    }
+
+    fn disassembly(&mut self, ui: &Ui, machine: &Machine) {
+        ui.window("Disassembly")
+            .position([300.0, 10.0], Condition::FirstUseEver)
+            .size([260.0, 440.0], Condition::FirstUseEver)
+            .build(|| {
+                let cpu = machine.cpu();
+                let pc = cpu.program_counter();
+                let memory = cpu.memory();
+                // Stay on the PC's alignment, so the instructions before it decode the same way
+                let start = pc.saturating_sub(DISASSEMBLY_BEFORE * 2) & !1 | pc & 1;
+                for address in (start..RAM - 1).step_by(2).take(DISASSEMBLY_BEFORE + DISASSEMBLY_AFTER + 1) {
+                    let opcode = u16::from_be_bytes([memory[address], memory[address + 1]]);
+                    let line = format!("{address:03X}  {opcode:04X}  {}", Instruction::decode(opcode));
+                    if address == pc {
+                        ui.text_colored(HIGHLIGHT, format!("> {line}"));
+                    } else {
+                        ui.text(format!("  {line}"));
+                    }
+                }
+            });
+    }
+
+    fn memory(&mut self, ui: &Ui, machine: &mut Machine) {
+        ui.window("Memory")
+            .position([570.0, 10.0], Condition::FirstUseEver)
+            .size([560.0, 440.0], Condition::FirstUseEver)
+            .build(|| {
+                let cpu = machine.cpu_mut();
+                let pc = cpu.program_counter();
+                let index = cpu.index_register() as usize;
+
+                let width = ui.push_item_width(ui.calc_text_size("000")[0] + 16.0);
+                let mut selected = self.selected as u16;
+                if hex_input(ui, "Address", &mut selected, "%03X") && (selected as usize) < RAM {
+                    self.selected = selected as usize;
+                    self.scroll_to_selected = true;
+                }
+                ui.same_line();
+                hex_input(ui, "Value", &mut cpu.memory_mut()[self.selected], "%02X");
+                ui.same_line();
+                if ui.button("Go to PC") {
+                    self.selected = pc.min(RAM - 1);
+                    self.scroll_to_selected = true;
+                }
+                ui.same_line();
+                if ui.button("Go to I") {
+                    self.selected = index.min(RAM - 1);
+                    self.scroll_to_selected = true;
+                }
+                ui.text_colored(HIGHLIGHT, "PC");
+                ui.same_line();
+                ui.text_colored(INDEX_COLOR, "I");
+                ui.same_line();
+                ui.text_disabled("click a byte to edit it");
+                width.end();
+
+                ui.child_window("bytes").build(|| {
+                    let row_height = ui.text_line_height_with_spacing();
+                    if self.scroll_to_selected {
+                        ui.set_scroll_y((self.selected / MEMORY_COLUMNS) as f32 * row_height);
+                        self.scroll_to_selected = false;
+                    }
+
+                    let byte_width = ui.calc_text_size("00")[0];
+                    let clipper = ListClipper::new((RAM / MEMORY_COLUMNS) as i32).begin(ui);
+                    for row in clipper.iter() {
+                        let start = row as usize * MEMORY_COLUMNS;
+                        ui.text_disabled(format!("{start:03X}"));
+                        for address in start..start + MEMORY_COLUMNS {
+                            ui.same_line();
+                            let color = if address == pc || address == pc + 1 {
+                                HIGHLIGHT
+                            } else if address == index {
+                                INDEX_COLOR
+                            } else {
+                                ui.style_color(StyleColor::Text)
+                            };
+                            let _color = ui.push_style_color(StyleColor::Text, color);
+                            let label = format!("{:02X}##{address}", cpu.memory()[address]);
+                            if ui
+                                .selectable_config(label)
+                                .selected(address == self.selected)
+                                .size([byte_width, 0.0])
+                                .build()
+                            {
+                                self.selected = address;
+                            }
+                        }
+                    }
+                });
+            });
+    }
+
+    fn keypad(&mut self, ui: &Ui, machine: &mut Machine) {
+        ui.window("Keypad")
+            .position([10.0, 560.0], Condition::FirstUseEver)
+            .always_auto_resize(true)
+            .build(|| {
+                let cpu = machine.cpu_mut();
+                let mut held = None;
+                for row in KEYPAD {
+                    for (column, key) in row.into_iter().enumerate() {
+                        if column > 0 {
+                            ui.same_line();
+                        }
+                        let color = if cpu.keys()[key] {
+                            ui.style_color(StyleColor::ButtonActive)
+                        } else {
+                            ui.style_color(StyleColor::Button)
+                        };
+                        let _color = ui.push_style_color(StyleColor::Button, color);
+                        ui.button_with_size(format!("{key:X}"), [32.0, 32.0]);
+                        if ui.is_item_active() {
+                            held = Some(key);
+                        }
+                    }
+                }
+
+                // Keys only go down and up with the mouse, keyboard presses are left alone
+                if held != self.clicked_key {
+                    if let Some(key) = self.clicked_key {
+                        cpu.keypress(key, false);
+                    }
+                    if let Some(key) = held {
+                        cpu.keypress(key, true);
+                    }
+                    self.clicked_key = held;
+                }
+            });
+    }
+
+    fn framebuffer(&mut self, ui: &Ui, machine: &Machine) {
+        ui.window("Framebuffer")
+            .position([300.0, 460.0], Condition::FirstUseEver)
+            .size([290.0, 180.0], Condition::FirstUseEver)
+            .build(|| {
+                let [available, _] = ui.content_region_avail();
+                let scale = (available / CHIP8_WIDTH as f32).floor().max(1.0);
+                let [left, top] = ui.cursor_screen_pos();
+                let (width, height) = (CHIP8_WIDTH as f32 * scale, CHIP8_HEIGHT as f32 * scale);
+
+                let draw_list = ui.get_window_draw_list();
+                draw_list
+                    .add_rect([left, top], [left + width, top + height], ImColor32::from_rgb(24, 24, 24))
+                    .filled(true)
+                    .build();
+                for (y, row) in machine.cpu().get_vram().iter().enumerate() {
+                    for (x, &pixel) in row.iter().enumerate() {
+                        if pixel != 0 {
+                            let (x, y) = (left + x as f32 * scale, top + y as f32 * scale);
+                            draw_list
+                                .add_rect([x, y], [x + scale, y + scale], ImColor32::WHITE)
+                                .filled(true)
+                                .build();
+                        }
+                    }
+                }
+
+                ui.dummy([width, height]);
+                if ui.is_item_hovered() {
+                    let [mouse_x, mouse_y] = ui.io().mouse_pos;
+                    let x = ((mouse_x - left) / scale) as usize;
+                    let y = ((mouse_y - top) / scale) as usize;
+                    ui.tooltip_text(format!("{x}, {y}"));
+                }
+            });
+    }
+}
+
+// A hex field, true when it was edited
+fn hex_input<T: imgui::internal::DataTypeKind>(ui: &Ui, label: &str, value: &mut T, format: &str) -> bool {
+    ui.input_scalar(label, value)
+        .display_format(format)
+        .chars_hexadecimal(true)
+        .build()
+}
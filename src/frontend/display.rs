@@ -1,11 +1,117 @@
 #![allow(dead_code)]
 
-use crate::cpu::{CHIP8_WIDTH, CHIP8_HEIGHT};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use chip8::cpu::{CHIP8_WIDTH, CHIP8_HEIGHT};
+
+use super::{
+    crt::Effect,
+    palette::{Palette, Rgb},
+};
 
 pub trait Display {
     fn draw(&mut self, vram: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]);
 }
 
+#[derive(Debug, Clone)]
+pub struct DisplaySettings {
+    pub render_scale: u32,
+    pub palette: Palette,
+    /// Phosphor decay every 1/60 s, 0 disables the afterglow
+    pub persistence: f32,
+    pub effect: Effect,
+    pub scaling: Scaling,
+    pub fullscreen: bool,
+    /// Pace frames by the display refresh instead of sleeping
+    pub vsync: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Scaling {
+    /// Whole multiples of the framebuffer size only
+    #[default]
+    Integer,
+    /// Fill as much of the window as the aspect ratio allows
+    Fractional,
+}
+
+impl Scaling {
+    /// Centres a `width` x `height` image in the output, keeping its aspect ratio.
+    ///
+    /// Returns the position and size of the image, the rest of the output is letterboxed.
+    pub fn fit(self, width: u32, height: u32, output_width: u32, output_height: u32) -> (i32, i32, u32, u32) {
+        let scale = (output_width as f32 / width as f32).min(output_height as f32 / height as f32);
+        let scale = match self {
+            // Never scale below 1x, even if the window is smaller than the framebuffer
+            Scaling::Integer => scale.floor().max(1.0),
+            Scaling::Fractional => scale,
+        };
+
+        let (w, h) = ((width as f32 * scale) as u32, (height as f32 * scale) as u32);
+        let x = (output_width as i32 - w as i32) / 2;
+        let y = (output_height as i32 - h as i32) / 2;
+        (x, y, w, h)
+    }
+}
+
+impl FromStr for Scaling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "integer" => Ok(Scaling::Integer),
+            "fractional" => Ok(Scaling::Fractional),
+            _ => Err(format!("unknown scaling '{s}', expected integer or fractional")),
+        }
+    }
+}
+
+impl TryFrom<String> for Scaling {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// The VRAM after palette lookup, stored row by row.
+#[derive(Debug)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgb>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Rgb(0, 0, 0); width * height],
+        }
+    }
+
+    /// Renders `vram` into the frame, resizing it when the machine switched between
+    /// low and high resolution. Returns whether the size changed.
+    pub fn render<R: AsRef<[u8]>>(&mut self, vram: &[R], palette: &Palette) -> bool {
+        let (width, height) = (vram.first().map_or(0, |row| row.as_ref().len()), vram.len());
+        let resized = (width, height) != (self.width, self.height);
+        if resized {
+            *self = Frame::new(width, height);
+        }
+
+        for (row, pixels) in vram.iter().zip(self.pixels.chunks_exact_mut(self.width.max(1))) {
+            for (&pixel, out) in row.as_ref().iter().zip(pixels) {
+                *out = palette.color(pixel);
+            }
+        }
+        resized
+    }
+}
+
 pub struct HeadlessDisplay;
 
 impl Display for HeadlessDisplay {
@@ -17,4 +123,30 @@ impl HeadlessDisplay {
     pub fn new() -> Self {
         Self
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_follows_the_vram_size() {
+        let mut frame = Frame::new(CHIP8_WIDTH, CHIP8_HEIGHT);
+        let palette = Palette::CLASSIC;
+
+        let mut lores = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        lores[1][2] = 1;
+        assert!(!frame.render(&lores, &palette));
+        assert_eq!(frame.pixels[CHIP8_WIDTH + 2], palette.color(1));
+
+        let mut hires = vec![vec![0; 2 * CHIP8_WIDTH]; 2 * CHIP8_HEIGHT];
+        hires[63][127] = 1;
+        assert!(frame.render(&hires, &palette));
+        assert_eq!((frame.width, frame.height), (128, 64));
+        assert_eq!(frame.pixels.len(), 128 * 64);
+        assert_eq!(frame.pixels[128 * 64 - 1], palette.color(1));
+
+        assert!(frame.render(&lores, &palette));
+        assert_eq!((frame.width, frame.height), (CHIP8_WIDTH, CHIP8_HEIGHT));
+        assert_eq!(frame.pixels[CHIP8_WIDTH + 2], palette.color(1));
+    }
+}
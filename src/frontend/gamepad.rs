@@ -0,0 +1,103 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use super::input::Chip8KeyCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum GamepadProfile {
+    /// D-pad on 2/4/6/8, the arrows of the hex keypad used by most games
+    #[default]
+    Keypad,
+    /// D-pad on 5/7/8/9, the keys under W/A/S/D in the QWERTY keymap
+    Wasd,
+}
+
+impl FromStr for GamepadProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "keypad" => Ok(GamepadProfile::Keypad),
+            "wasd" => Ok(GamepadProfile::Wasd),
+            _ => Err(format!("unknown gamepad profile '{s}', expected keypad or wasd")),
+        }
+    }
+}
+
+impl TryFrom<String> for GamepadProfile {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+// Button names as used by SDL's GameController API
+const KEYPAD: [(&str, u8); 10] = [
+    ("dpup", 0x2),
+    ("dpleft", 0x4),
+    ("dpright", 0x6),
+    ("dpdown", 0x8),
+    ("a", 0x5),
+    ("b", 0x0),
+    ("x", 0xa),
+    ("y", 0xb),
+    ("back", 0xc),
+    ("start", 0xf),
+];
+
+const WASD: [(&str, u8); 10] = [
+    ("dpup", 0x5),
+    ("dpleft", 0x7),
+    ("dpdown", 0x8),
+    ("dpright", 0x9),
+    ("a", 0x6),
+    ("b", 0x4),
+    ("x", 0xa),
+    ("y", 0xb),
+    ("back", 0xc),
+    ("start", 0xf),
+];
+
+/// Maps gamepad buttons, by their SDL name, to the hex keypad.
+///
+/// The left stick acts as the d-pad.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ButtonMap {
+    bindings: Vec<(String, Chip8KeyCode)>,
+}
+
+impl ButtonMap {
+    pub fn profile(profile: GamepadProfile) -> Self {
+        let buttons = match profile {
+            GamepadProfile::Keypad => KEYPAD,
+            GamepadProfile::Wasd => WASD,
+        };
+
+        Self {
+            bindings: buttons
+                .iter()
+                .map(|&(name, hex)| (name.to_owned(), Chip8KeyCode::from_hex(hex).unwrap()))
+                .collect(),
+        }
+    }
+
+    /// Replaces the buttons bound to `key`.
+    pub fn bind(&mut self, key: Chip8KeyCode, buttons: &[String]) {
+        self.bindings.retain(|(_, bound)| *bound != key);
+        self.bindings
+            .extend(buttons.iter().map(|name| (name.clone(), key)));
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, Chip8KeyCode)> {
+        self.bindings.iter().map(|(name, key)| (name.as_str(), *key))
+    }
+}
+
+impl Default for ButtonMap {
+    fn default() -> Self {
+        Self::profile(GamepadProfile::default())
+    }
+}
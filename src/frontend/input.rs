@@ -1,6 +1,10 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
 pub trait Input {
-    fn new() -> Self;
-    fn input_loop(&mut self) -> (Option<Chip8KeyCode>, bool);
+    /// Returns every event that arrived since the last call, oldest first.
+    fn input_loop(&mut self) -> Vec<InputEvent>;
     fn decode_input(input: Chip8KeyCode) -> usize {
         match input {
             Chip8KeyCode::One => 1,
@@ -19,11 +23,12 @@ pub trait Input {
             Chip8KeyCode::Zero => 0,
             Chip8KeyCode::B => 0xb,
             Chip8KeyCode::F => 0xf,
-            Chip8KeyCode::Exit => 0,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
 pub enum Chip8KeyCode {
     One,
     Two,
@@ -41,5 +46,66 @@ pub enum Chip8KeyCode {
     Zero,
     B,
     F,
-    Exit
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    /// A hex keypad key was pressed (`true`) or released
+    Key(Chip8KeyCode, bool),
+    Quit,
+    NextEffect,
+    ToggleFullscreen,
+    SpeedDown,
+    SpeedUp,
+    ToggleTurbo,
+    ToggleDebugger,
+    /// New window size in pixels
+    Resized(u32, u32),
+    FocusGained,
+    FocusLost,
+}
+
+impl Chip8KeyCode {
+    pub fn from_hex(digit: u8) -> Option<Self> {
+        match digit {
+            0x0 => Some(Chip8KeyCode::Zero),
+            0x1 => Some(Chip8KeyCode::One),
+            0x2 => Some(Chip8KeyCode::Two),
+            0x3 => Some(Chip8KeyCode::Three),
+            0x4 => Some(Chip8KeyCode::Four),
+            0x5 => Some(Chip8KeyCode::Five),
+            0x6 => Some(Chip8KeyCode::Six),
+            0x7 => Some(Chip8KeyCode::Seven),
+            0x8 => Some(Chip8KeyCode::Eight),
+            0x9 => Some(Chip8KeyCode::Nine),
+            0xa => Some(Chip8KeyCode::A),
+            0xb => Some(Chip8KeyCode::B),
+            0xc => Some(Chip8KeyCode::C),
+            0xd => Some(Chip8KeyCode::D),
+            0xe => Some(Chip8KeyCode::E),
+            0xf => Some(Chip8KeyCode::F),
+            _ => None,
+        }
+    }
+}
+
+// Keypad keys are written as a single hex digit
+impl FromStr for Chip8KeyCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u8::from_str_radix(s, 16)
+            .ok()
+            .filter(|_| s.len() == 1)
+            .and_then(Chip8KeyCode::from_hex)
+            .ok_or_else(|| format!("invalid CHIP-8 key '{s}', expected a hex digit 0-F"))
+    }
+}
+
+impl TryFrom<String> for Chip8KeyCode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
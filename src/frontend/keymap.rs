@@ -0,0 +1,94 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use super::input::Chip8KeyCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum KeymapPreset {
+    #[default]
+    Qwerty,
+    Azerty,
+    Numpad,
+}
+
+impl FromStr for KeymapPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "qwerty" => Ok(KeymapPreset::Qwerty),
+            "azerty" => Ok(KeymapPreset::Azerty),
+            "numpad" => Ok(KeymapPreset::Numpad),
+            _ => Err(format!("unknown keymap '{s}', expected qwerty, azerty or numpad")),
+        }
+    }
+}
+
+impl TryFrom<String> for KeymapPreset {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+// Host keys for the hex keypad, in the layout of the COSMAC VIP:
+// 1 2 3 C
+// 4 5 6 D
+// 7 8 9 E
+// A 0 B F
+const KEYPAD: [u8; 16] = [0x1, 0x2, 0x3, 0xc, 0x4, 0x5, 0x6, 0xd, 0x7, 0x8, 0x9, 0xe, 0xa, 0x0, 0xb, 0xf];
+
+const QWERTY: [&str; 16] = ["1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V"];
+
+const AZERTY: [&str; 16] = ["1", "2", "3", "4", "A", "Z", "E", "R", "Q", "S", "D", "F", "W", "X", "C", "V"];
+
+const NUMPAD: [&str; 16] = [
+    "Keypad 7", "Keypad 8", "Keypad 9", "Keypad /",
+    "Keypad 4", "Keypad 5", "Keypad 6", "Keypad *",
+    "Keypad 1", "Keypad 2", "Keypad 3", "Keypad -",
+    "Keypad 0", "Keypad .", "Keypad Enter", "Keypad +",
+];
+
+/// Maps host keys, by their SDL key name, to the hex keypad.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: Vec<(String, Chip8KeyCode)>,
+}
+
+impl Keymap {
+    pub fn preset(preset: KeymapPreset) -> Self {
+        let keys = match preset {
+            KeymapPreset::Qwerty => QWERTY,
+            KeymapPreset::Azerty => AZERTY,
+            KeymapPreset::Numpad => NUMPAD,
+        };
+
+        Self {
+            bindings: keys
+                .iter()
+                .zip(KEYPAD)
+                .map(|(&name, hex)| (name.to_owned(), Chip8KeyCode::from_hex(hex).unwrap()))
+                .collect(),
+        }
+    }
+
+    /// Replaces the host keys bound to `key`.
+    pub fn bind(&mut self, key: Chip8KeyCode, host_keys: &[String]) {
+        self.bindings.retain(|(_, bound)| *bound != key);
+        self.bindings
+            .extend(host_keys.iter().map(|name| (name.clone(), key)));
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, Chip8KeyCode)> {
+        self.bindings.iter().map(|(name, key)| (name.as_str(), *key))
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::preset(KeymapPreset::default())
+    }
+}
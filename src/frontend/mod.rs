@@ -1,5 +1,11 @@
+pub mod crt;
+pub mod debugger;
 pub mod display;
+pub mod gamepad;
 pub mod input;
+pub mod keymap;
+pub mod palette;
+pub mod phosphor;
 use display::Display;
 use input::Input;
 
@@ -8,5 +14,4 @@ pub trait Frontend {
     type Input: Input;
     fn display(&mut self) -> &mut Self::Display;
     fn input(&mut self) -> &mut Self::Input;
-    fn new(render_scale: u32) -> Self;
 }
\ No newline at end of file
@@ -0,0 +1,194 @@
+use std::{fmt, str::FromStr};
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    // Accepts RRGGBB, optionally prefixed with '#'
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        // from_str_radix would also take a '+' sign, so check the digits first
+        if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("invalid colour '{s}', expected RRGGBB"));
+        }
+
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Rgb(channel(0), channel(2), channel(4)))
+    }
+}
+
+impl TryFrom<String> for Rgb {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.0, self.1, self.2)
+    }
+}
+
+/// Colours used to render the framebuffer, indexed by pixel value.
+///
+/// Index 0 is the background and index 1 the foreground. XO-CHIP draws to two
+/// bitplanes, so index 2 is used for pixels lit only in the second plane and
+/// index 3 for pixels lit in both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Palette {
+    colors: [Rgb; 4],
+}
+
+impl Palette {
+    pub const CLASSIC: Palette = Palette::new([
+        Rgb(0x00, 0x00, 0x00),
+        Rgb(0xFF, 0xFF, 0xFF),
+        Rgb(0xAA, 0xAA, 0xAA),
+        Rgb(0x55, 0x55, 0x55),
+    ]);
+
+    pub const AMBER: Palette = Palette::new([
+        Rgb(0x1A, 0x0E, 0x00),
+        Rgb(0xFF, 0xB0, 0x00),
+        Rgb(0xB3, 0x6B, 0x00),
+        Rgb(0xFF, 0xE0, 0x80),
+    ]);
+
+    pub const GREEN_PHOSPHOR: Palette = Palette::new([
+        Rgb(0x00, 0x14, 0x00),
+        Rgb(0x33, 0xFF, 0x33),
+        Rgb(0x1A, 0x99, 0x1A),
+        Rgb(0xB3, 0xFF, 0xB3),
+    ]);
+
+    // Game Boy greens with the lightest shade as the background. Its second
+    // lightest shade is too close to that to draw with, so the foreground is the
+    // darkest shade, the second plane the darker mid shade, and pixels lit in
+    // both planes a shade between that and the background.
+    pub const LCD: Palette = Palette::new([
+        Rgb(0x9B, 0xBC, 0x0F),
+        Rgb(0x0F, 0x38, 0x0F),
+        Rgb(0x30, 0x62, 0x30),
+        Rgb(0x5A, 0x7A, 0x1F),
+    ]);
+
+    pub const HIGH_CONTRAST: Palette = Palette::new([
+        Rgb(0x00, 0x00, 0x00),
+        Rgb(0xFF, 0xFF, 0x00),
+        Rgb(0x00, 0xFF, 0xFF),
+        Rgb(0xFF, 0x00, 0xFF),
+    ]);
+
+    pub const NAMES: [&'static str; 5] = ["classic", "amber", "green", "lcd", "high-contrast"];
+
+    pub const fn new(colors: [Rgb; 4]) -> Self {
+        Self { colors }
+    }
+
+    pub fn named(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "classic" => Some(Self::CLASSIC),
+            "amber" => Some(Self::AMBER),
+            "green" | "green-phosphor" => Some(Self::GREEN_PHOSPHOR),
+            "lcd" => Some(Self::LCD),
+            "high-contrast" => Some(Self::HIGH_CONTRAST),
+            _ => None,
+        }
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    pub fn set_background(&mut self, color: Rgb) {
+        self.colors[0] = color;
+    }
+
+    pub fn set_foreground(&mut self, color: Rgb) {
+        self.colors[1] = color;
+    }
+
+    pub fn color(&self, pixel: u8) -> Rgb {
+        self.colors[(pixel & 0b11) as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::CLASSIC
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::named(s).ok_or_else(|| {
+            format!("unknown palette '{s}', expected one of: {}", Self::NAMES.join(", "))
+        })
+    }
+}
+
+impl TryFrom<String> for Palette {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn luma(Rgb(r, g, b): Rgb) -> u32 {
+        (299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000
+    }
+
+    #[test]
+    fn parses_hex_colours() {
+        assert_eq!("9BBC0F".parse(), Ok(Rgb(0x9B, 0xBC, 0x0F)));
+        assert_eq!("#ff8000".parse(), Ok(Rgb(0xFF, 0x80, 0x00)));
+        assert_eq!(Rgb(0x0F, 0x38, 0x0F).to_string().parse(), Ok(Rgb(0x0F, 0x38, 0x0F)));
+    }
+
+    #[test]
+    fn rejects_malformed_colours() {
+        for s in ["", "#", "FFF", "#FFFFFFF", "GG0000", "+F0000", "#12345é"] {
+            assert_eq!(
+                s.parse::<Rgb>(),
+                Err(format!("invalid colour '{s}', expected RRGGBB")),
+                "{s:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn looks_up_palettes_by_name() {
+        for name in Palette::NAMES {
+            assert!(Palette::named(name).is_some(), "{name}");
+        }
+        assert_eq!("LCD".parse(), Ok(Palette::LCD));
+        assert_eq!("green-phosphor".parse(), Ok(Palette::GREEN_PHOSPHOR));
+        assert_eq!(
+            "sepia".parse::<Palette>(),
+            Err("unknown palette 'sepia', expected one of: classic, amber, green, lcd, high-contrast".to_string())
+        );
+    }
+
+    #[test]
+    fn lcd_shades_are_all_darker_than_the_background() {
+        let background = luma(Palette::LCD.background());
+        for pixel in 1..4 {
+            assert!(luma(Palette::LCD.color(pixel)) + 48 < background, "index {pixel}");
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Instant;
+
+use chip8::machine::TIMER_HZ;
+
+use super::palette::Rgb;
+
+/// Emulates the afterglow of a CRT phosphor.
+///
+/// CHIP-8 games erase and redraw sprites with XOR, so moving objects are
+/// briefly invisible and flicker. Instead of switching off at once, pixels
+/// that return to the background colour fade out over several frames.
+///
+/// The fade follows the wall clock, so it looks the same whatever the
+/// refresh rate and however many frames get dropped.
+#[derive(Debug)]
+pub struct PhosphorFilter {
+    // Fraction of the remaining glow kept every 1/60 s
+    decay: f32,
+    glow: Vec<[f32; 3]>,
+    last_apply: Option<Instant>,
+}
+
+impl PhosphorFilter {
+    pub const MAX_DECAY: f32 = 0.95;
+
+    pub fn new(decay: f32) -> Self {
+        Self {
+            decay: decay.clamp(0.0, Self::MAX_DECAY),
+            glow: Vec::new(),
+            last_apply: None,
+        }
+    }
+
+    pub fn apply(&mut self, pixels: &mut [Rgb], background: Rgb) {
+        if self.glow.len() != pixels.len() {
+            self.glow = pixels.iter().map(|&p| channels(p)).collect();
+        }
+
+        let now = Instant::now();
+        let ticks = self.last_apply.map_or(1.0, |last| (now - last).as_secs_f32() * TIMER_HZ as f32);
+        self.last_apply = Some(now);
+        let decay = self.decay.powf(ticks);
+
+        let bg = channels(background);
+        for (pixel, glow) in pixels.iter_mut().zip(self.glow.iter_mut()) {
+            if *pixel != background {
+                // Lit pixels show up at full brightness immediately
+                *glow = channels(*pixel);
+                continue;
+            }
+
+            for (channel, bg) in glow.iter_mut().zip(bg) {
+                *channel = bg + (*channel - bg) * decay;
+                if (*channel - bg).abs() < 1.0 {
+                    *channel = bg;
+                }
+            }
+            *pixel = Rgb(glow[0].round() as u8, glow[1].round() as u8, glow[2].round() as u8);
+        }
+    }
+}
+
+fn channels(rgb: Rgb) -> [f32; 3] {
+    [rgb.0 as f32, rgb.1 as f32, rgb.2 as f32]
+}
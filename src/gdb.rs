@@ -0,0 +1,428 @@
+//! A GDB remote serial protocol stub, so ROMs can be debugged with existing tools.
+//!
+//! The registers are V0-VF (8 bits each), I and PC (16 bits), then SP, DT
+//! and ST (8 bits), numbered 0 to 20 in that order. GDB has no CHIP-8
+//! architecture, so the layout is also served as a target description.
+//!
+//! Breakpoints don't patch memory, the stub checks the PC before every
+//! instruction while continuing. The timers tick after every
+//! `instructions_per_frame` instructions rather than in real time.
+
+use std::{
+    collections::HashSet,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::TcpStream,
+};
+
+use crate::{
+    cpu::{CpuError, CPU, RAM},
+    machine::DEFAULT_INSTRUCTIONS_PER_FRAME,
+};
+
+const REGISTER_COUNT: usize = 21;
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+
+// How many instructions run between checks for an interrupt from GDB
+const INTERRUPT_POLL: usize = 1000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Why execution stopped, as reported to GDB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    Breakpoint,
+    Interrupted,
+    Fault(CpuError),
+}
+
+impl Stop {
+    fn signal(self) -> u8 {
+        match self {
+            Stop::Breakpoint => SIGTRAP,
+            Stop::Interrupted => SIGINT,
+            Stop::Fault(CpuError::UnknownOpcode { .. }) => SIGILL,
+            Stop::Fault(_) => SIGSEGV,
+        }
+    }
+}
+
+/// Serves a CPU to one GDB connection at a time.
+#[derive(Debug)]
+pub struct GdbStub {
+    cpu: CPU,
+    breakpoints: HashSet<usize>,
+    instructions_per_frame: u32,
+    // Instructions run since the timers last ticked
+    frame_instructions: u32,
+    last_stop: Option<Stop>,
+    ack: bool,
+}
+
+impl GdbStub {
+    pub fn new(cpu: CPU) -> Self {
+        Self {
+            cpu,
+            breakpoints: HashSet::new(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            frame_instructions: 0,
+            last_stop: None,
+            ack: true,
+        }
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame.max(1);
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn into_cpu(self) -> CPU {
+        self.cpu
+    }
+
+    /// Handles packets until GDB detaches, kills the target or disconnects.
+    pub fn serve(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        self.ack = true;
+
+        while let Some(packet) = read_packet(&mut reader, &mut writer, self.ack)? {
+
+            let reply = match packet.as_str() {
+                "k" => return Ok(()),
+                "D" => {
+                    send(&mut writer, "OK")?;
+                    return Ok(());
+                }
+                "c" | "s" => {
+                    let stop = self.run(&mut reader, packet == "s")?;
+                    if let Stop::Fault(e) = stop {
+                        // Shown on the GDB console
+                        send(&mut writer, &format!("O{}", to_hex(format!("{e}\n").as_bytes())))?;
+                    }
+                    self.stop_reply(stop)
+                }
+                _ => self.handle(&packet),
+            };
+            send(&mut writer, &reply)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str) -> String {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => Some(self.stop_reply(self.last_stop.unwrap_or(Stop::Breakpoint))),
+            Some(b'g') => Some(self.read_registers()),
+            Some(b'G') => self.write_registers(&packet[1..]),
+            Some(b'p') => self.read_register(&packet[1..]),
+            Some(b'P') => self.write_register(&packet[1..]),
+            Some(b'm') => self.read_memory(&packet[1..]),
+            Some(b'M') => self.write_memory(&packet[1..]),
+            Some(b'Z') => self.breakpoint(&packet[1..], true),
+            Some(b'z') => self.breakpoint(&packet[1..], false),
+            Some(b'H') => Some("OK".to_owned()),
+            Some(b'q' | b'Q' | b'v') => self.query(packet),
+            _ => None,
+        };
+        // An empty reply tells GDB the packet isn't supported
+        reply.unwrap_or_default()
+    }
+
+    fn query(&mut self, packet: &str) -> Option<String> {
+        if packet.starts_with("qSupported") {
+            return Some("PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_owned());
+        }
+        if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = request.split_once(',')?;
+            let offset = usize::from_str_radix(offset, 16).ok()?.min(TARGET_XML.len());
+            let length = usize::from_str_radix(length, 16).ok()?;
+            let Some(end) = offset.checked_add(length) else {
+                return Some("E01".to_owned());
+            };
+            let end = end.min(TARGET_XML.len());
+            let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+            return Some(format!("{marker}{}", &TARGET_XML[offset..end]));
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.ack = false;
+                Some("OK".to_owned())
+            }
+            "qAttached" => Some("1".to_owned()),
+            "qC" => Some("QC1".to_owned()),
+            "qfThreadInfo" => Some("m1".to_owned()),
+            "qsThreadInfo" => Some("l".to_owned()),
+            _ => None,
+        }
+    }
+
+    fn stop_reply(&mut self, stop: Stop) -> String {
+        self.last_stop = Some(stop);
+        format!("S{:02x}", stop.signal())
+    }
+
+    // Runs until a breakpoint, a fault or an interrupt from GDB, or for one instruction
+    fn run(&mut self, reader: &mut BufReader<TcpStream>, single_step: bool) -> io::Result<Stop> {
+        let mut executed = 0;
+        loop {
+            // The instruction under a breakpoint runs when continuing from it
+            if executed > 0 && self.breakpoints.contains(&self.cpu.program_counter()) {
+                return Ok(Stop::Breakpoint);
+            }
+            if executed > 0 && executed % INTERRUPT_POLL == 0 && interrupted(reader)? {
+                return Ok(Stop::Interrupted);
+            }
+
+            if let Err(e) = self.cpu.cycle() {
+                return Ok(Stop::Fault(e));
+            }
+            executed += 1;
+
+            self.frame_instructions += 1;
+            if self.frame_instructions >= self.instructions_per_frame {
+                self.frame_instructions = 0;
+                self.cpu.cycle_timers();
+            }
+
+            if single_step {
+                return Ok(Stop::Breakpoint);
+            }
+        }
+    }
+
+    fn register(&self, register: usize) -> Vec<u8> {
+        match register {
+            0..=15 => vec![self.cpu.registers()[register]],
+            REG_I => self.cpu.index_register().to_le_bytes().to_vec(),
+            REG_PC => (self.cpu.program_counter() as u16).to_le_bytes().to_vec(),
+            REG_SP => vec![self.cpu.stack().len() as u8],
+            REG_DT => vec![self.cpu.delay_timer()],
+            _ => vec![self.cpu.sound_timer()],
+        }
+    }
+
+    // The stack pointer can't be set, there is nothing to fill the stack with
+    fn set_register(&mut self, register: usize, bytes: &[u8]) -> bool {
+        let word = || bytes.try_into().ok().map(u16::from_le_bytes);
+        match (register, bytes) {
+            (0..=15, &[value]) => self.cpu.registers_mut()[register] = value,
+            (REG_I, _) if bytes.len() == 2 => self.cpu.set_index_register(word().unwrap()),
+            (REG_PC, _) if bytes.len() == 2 => self.cpu.set_program_counter(word().unwrap() as usize),
+            (REG_SP, &[value]) => return value as usize == self.cpu.stack().len(),
+            (REG_DT, &[value]) => self.cpu.set_delay_timer(value),
+            (REG_ST, &[value]) => self.cpu.set_sound_timer(value),
+            _ => return false,
+        }
+        true
+    }
+
+    fn register_size(register: usize) -> usize {
+        match register {
+            REG_I | REG_PC => 2,
+            _ => 1,
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTER_COUNT).map(|register| to_hex(&self.register(register))).collect()
+    }
+
+    fn write_registers(&mut self, data: &str) -> Option<String> {
+        let bytes = from_hex(data)?;
+        let mut offset = 0;
+        for register in 0..REGISTER_COUNT {
+            let size = Self::register_size(register);
+            let value = bytes.get(offset..offset + size)?;
+            // SP can't be set, GDB just sends it back when writing all registers
+            if register != REG_SP && !self.set_register(register, value) {
+                return Some("E01".to_owned());
+            }
+            offset += size;
+        }
+        Some("OK".to_owned())
+    }
+
+    fn read_register(&self, args: &str) -> Option<String> {
+        let register = usize::from_str_radix(args, 16).ok()?;
+        if register >= REGISTER_COUNT {
+            return Some("E01".to_owned());
+        }
+        Some(to_hex(&self.register(register)))
+    }
+
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (register, value) = args.split_once('=')?;
+        let register = usize::from_str_radix(register, 16).ok()?;
+        let ok = register < REGISTER_COUNT && self.set_register(register, &from_hex(value)?);
+        Some(if ok { "OK" } else { "E01" }.to_owned())
+    }
+
+    fn memory_range(args: &str) -> Option<(usize, usize)> {
+        let (address, length) = args.split_once(',')?;
+        let address = usize::from_str_radix(address, 16).ok()?;
+        let length = usize::from_str_radix(length, 16).ok()?;
+        address
+            .checked_add(length)
+            .filter(|&end| end <= RAM)
+            .map(|_| (address, length))
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let Some((address, length)) = Self::memory_range(args) else {
+            return Some("E01".to_owned());
+        };
+        Some(to_hex(&self.cpu.memory()[address..address + length]))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let bytes = from_hex(data)?;
+        match Self::memory_range(range) {
+            Some((address, length)) if length == bytes.len() => {
+                self.cpu.memory_mut()[address..address + length].copy_from_slice(&bytes);
+                Some("OK".to_owned())
+            }
+            _ => Some("E01".to_owned()),
+        }
+    }
+
+    // Only software breakpoints, type 0, are supported
+    fn breakpoint(&mut self, args: &str, insert: bool) -> Option<String> {
+        let mut fields = args.split(',');
+        if fields.next()? != "0" {
+            return None;
+        }
+        let address = usize::from_str_radix(fields.next()?, 16).ok()?;
+        if insert {
+            self.breakpoints.insert(address);
+        } else {
+            self.breakpoints.remove(&address);
+        }
+        Some("OK".to_owned())
+    }
+}
+
+/// Reads the next `$packet#checksum`, skipping acks. `None` when the connection closed.
+///
+/// While acknowledging, packets are answered with `+`, or with `-` and
+/// dropped when the checksum doesn't match so GDB sends them again.
+fn read_packet(reader: &mut impl BufRead, writer: &mut impl Write, ack: bool) -> io::Result<Option<String>> {
+    loop {
+        let mut byte = [0];
+        loop {
+            if reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            // Acks, and interrupts that came in after the target already stopped
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+
+        let mut packet = Vec::new();
+        if reader.read_until(b'#', &mut packet)? == 0 || packet.pop() != Some(b'#') {
+            return Ok(None);
+        }
+        let mut checksum = [0; 2];
+        reader.read_exact(&mut checksum)?;
+
+        if ack {
+            let expected = packet.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            let received = std::str::from_utf8(&checksum).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let valid = received == Some(expected);
+            writer.write_all(if valid { b"+" } else { b"-" })?;
+            writer.flush()?;
+            if !valid {
+                continue;
+            }
+        }
+        return String::from_utf8(packet)
+            .map(Some)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e));
+    }
+}
+
+fn send(writer: &mut impl Write, packet: &str) -> io::Result<()> {
+    let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    write!(writer, "${packet}#{checksum:02x}")?;
+    writer.flush()
+}
+
+// Checks for the 0x03 byte GDB sends to stop a running target, without blocking
+fn interrupted(reader: &mut BufReader<TcpStream>) -> io::Result<bool> {
+    if !reader.buffer().is_empty() {
+        let interrupt = reader.buffer()[0] == 0x03;
+        if interrupt {
+            reader.consume(1);
+        }
+        return Ok(interrupt);
+    }
+
+    let stream = reader.get_ref();
+    stream.set_nonblocking(true)?;
+    let mut byte = [0];
+    let result = stream.peek(&mut byte);
+    stream.set_nonblocking(false)?;
+    match result {
+        Ok(1) if byte[0] == 0x03 => {
+            reader.get_mut().read_exact(&mut byte)?;
+            Ok(true)
+        }
+        Ok(_) => Ok(false),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,13 @@
+//! The emulator core: CPU, machine timing and font, free of any frontend.
+
+pub mod coverage;
+pub mod cpu;
+pub mod disasm;
+pub mod font;
+pub mod gdb;
+pub mod machine;
+pub mod monitor;
+pub mod profiler;
+pub mod timing;
+pub mod trace;
+pub mod tracer;
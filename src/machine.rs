@@ -0,0 +1,257 @@
+use crate::cpu::{CpuError, CPU};
+use crate::timing::{vip_cycles, Timing, VIP_CYCLES_PER_FRAME, VIP_DISPLAY_CYCLES};
+
+/// Rate of the delay and sound timers, and of emulated frames.
+pub const TIMER_HZ: u32 = 60;
+
+/// 720 instructions per second, which suits most CHIP-8 games.
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 12;
+
+pub const MIN_SPEED: f32 = 0.25;
+pub const MAX_SPEED: f32 = 16.0;
+
+/// The CPU driven by an emulated 60 Hz clock.
+///
+/// Each frame runs a fixed number of instructions, or a fixed number of VIP
+/// machine cycles, and then ticks the timers exactly once, so game speed and
+/// timer rate don't depend on how often the host calls `run_frame`.
+#[derive(Debug)]
+pub struct Machine {
+    cpu: CPU,
+    timing: Timing,
+    instructions_per_frame: u32,
+    // Multiplier for the work done per frame, the timers always tick once per frame
+    speed: f32,
+    // Instructions or cycles carried over to the next frame
+    budget: f32,
+}
+
+impl Machine {
+    pub fn new(cpu: CPU, instructions_per_frame: u32) -> Self {
+        Self {
+            cpu,
+            timing: Timing::Instructions,
+            instructions_per_frame,
+            speed: 1.0,
+            budget: 0.0,
+        }
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.budget = 0.0;
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn instructions_per_frame(&self) -> u32 {
+        self.instructions_per_frame
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    pub fn faster(&mut self) {
+        self.set_speed(self.speed * 2.0);
+    }
+
+    pub fn slower(&mut self) {
+        self.set_speed(self.speed / 2.0);
+    }
+
+    /// Runs one 1/60 s frame and returns the sound timer after it ticked.
+    ///
+    /// On an error the CPU is left on the faulting instruction and the timers
+    /// don't tick.
+    pub fn run_frame(&mut self) -> Result<u8, CpuError> {
+        match self.timing {
+            Timing::Instructions => self.run_instructions()?,
+            Timing::Vip => self.run_vip_cycles()?,
+        }
+        Ok(self.cpu.cycle_timers())
+    }
+
+    fn run_instructions(&mut self) -> Result<(), CpuError> {
+        self.budget += self.instructions_per_frame as f32 * self.speed;
+        while self.budget >= 1.0 {
+            self.budget -= 1.0;
+            let opcode = self.cpu.cycle()?;
+            if self.waits_for_display(opcode) {
+                self.budget = self.budget.fract();
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn run_vip_cycles(&mut self) -> Result<(), CpuError> {
+        self.budget += (VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES) as f32 * self.speed;
+        loop {
+            // Instructions that don't fit run in the next frame, with the budget carried over
+            let cost = vip_cycles(&self.cpu, self.cpu.peek_opcode()) as f32;
+            if cost > self.budget {
+                break;
+            }
+            self.budget -= cost;
+
+            let opcode = self.cpu.cycle()?;
+            if self.waits_for_display(opcode) {
+                self.budget = 0.0;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // With the display wait quirk, or on the VIP, the rest of the frame is spent waiting for the interrupt
+    fn waits_for_display(&self, opcode: u16) -> bool {
+        let waits = self.cpu.quirks().display_wait || self.timing == Timing::Vip;
+        waits && opcode & 0xF000 == 0xD000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Quirks;
+
+    // 200: V0 += 1, 202: jump 200
+    const COUNT: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    fn machine(rom: &[u8], quirks: Quirks) -> Machine {
+        let mut cpu = CPU::with_quirks(quirks);
+        cpu.load_rom(rom);
+        Machine::new(cpu, DEFAULT_INSTRUCTIONS_PER_FRAME)
+    }
+
+    fn counter(machine: &Machine) -> u8 {
+        machine.cpu().registers()[0]
+    }
+
+    #[test]
+    fn runs_a_frame_of_instructions_and_ticks_the_timers_once() {
+        let mut machine = machine(&COUNT, Quirks::default());
+        machine.cpu_mut().set_delay_timer(5);
+        machine.cpu_mut().set_sound_timer(3);
+
+        assert_eq!(machine.run_frame(), Ok(2));
+        assert_eq!(counter(&machine), 6);
+        assert_eq!(machine.cpu().delay_timer(), 4);
+    }
+
+    #[test]
+    fn fractional_speeds_carry_over() {
+        let mut machine = machine(&COUNT, Quirks::default());
+        machine.set_instructions_per_frame(10);
+        machine.set_speed(0.25);
+
+        // 2.5 instructions per frame, so 2 and then 3
+        machine.run_frame().unwrap();
+        assert_eq!(counter(&machine), 1);
+        assert_eq!(machine.cpu().program_counter(), 0x200);
+        machine.run_frame().unwrap();
+        assert_eq!(counter(&machine), 3);
+        assert_eq!(machine.cpu().program_counter(), 0x202);
+    }
+
+    #[test]
+    fn display_wait_ends_the_frame_at_a_draw() {
+        // 200: V0 += 1, 202: draw, 204: jump 200
+        let rom = [0x70, 0x01, 0xD1, 0x11, 0x12, 0x00];
+        let mut machine = machine(
+            &rom,
+            Quirks {
+                display_wait: true,
+                ..Quirks::default()
+            },
+        );
+
+        machine.run_frame().unwrap();
+        assert_eq!(machine.cpu().program_counter(), 0x204);
+        machine.run_frame().unwrap();
+        assert_eq!(counter(&machine), 2);
+
+        // Without the quirk a frame runs all its instructions
+        let mut machine = self::machine(&rom, Quirks::default());
+        machine.run_frame().unwrap();
+        assert_eq!(counter(&machine), 4);
+    }
+
+    #[test]
+    fn waits_for_display_only_on_draws() {
+        let machine = self::machine(
+            &COUNT,
+            Quirks {
+                display_wait: true,
+                ..Quirks::default()
+            },
+        );
+        assert!(machine.waits_for_display(0xD125));
+        assert!(!machine.waits_for_display(0x00E0));
+        assert!(!machine.waits_for_display(0xA000));
+
+        // The default presets don't wait, unless the timing is the VIP's
+        let mut machine = self::machine(&COUNT, Quirks::default());
+        assert!(!machine.waits_for_display(0xD125));
+        machine.set_timing(Timing::Vip);
+        assert!(machine.waits_for_display(0xD125));
+    }
+
+    #[test]
+    fn vip_timing_draws_once_per_frame() {
+        // 200: V0 += 1, 202: draw, 204: jump 200
+        let mut machine = machine(&[0x70, 0x01, 0xD1, 0x11, 0x12, 0x00], Quirks::default());
+        machine.set_timing(Timing::Vip);
+
+        for frame in 1..=3 {
+            machine.run_frame().unwrap();
+            assert_eq!(counter(&machine), frame);
+            assert_eq!(machine.cpu().program_counter(), 0x204);
+        }
+    }
+
+    #[test]
+    fn vip_timing_spends_a_cycle_budget() {
+        let mut machine = machine(&COUNT, Quirks::default());
+        machine.set_timing(Timing::Vip);
+        let cost = vip_cycles(machine.cpu(), 0x7001) + vip_cycles(machine.cpu(), 0x1200);
+        let budget = VIP_CYCLES_PER_FRAME - VIP_DISPLAY_CYCLES;
+
+        machine.run_frame().unwrap();
+        assert_eq!(counter(&machine) as u32, budget / cost);
+        // What's left over carries into the next frame
+        machine.run_frame().unwrap();
+        assert_eq!(counter(&machine) as u32, 2 * budget / cost + 1);
+    }
+
+    #[test]
+    fn faults_stop_the_frame_without_ticking_the_timers() {
+        // 200: V0 += 1, 202: return with an empty stack
+        let mut machine = machine(&[0x70, 0x01, 0x00, 0xEE], Quirks::default());
+        machine.cpu_mut().set_delay_timer(5);
+
+        assert_eq!(machine.run_frame(), Err(CpuError::StackUnderflow { pc: 0x202 }));
+        assert_eq!(machine.cpu().program_counter(), 0x202);
+        assert_eq!(machine.cpu().delay_timer(), 5);
+        assert_eq!(counter(&machine), 1);
+    }
+}
@@ -2,48 +2,189 @@ use crate::frontend::display::Display;
 extern crate sdl2;
 extern crate imgui;
 
-use frontend::input::{Chip8KeyCode, Input};
+use clap::Parser;
+use frontend::input::{Input, InputEvent};
 use sdl::SDL2Frontend;
-use std::time::{Duration, Instant};
 
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
 
-pub mod cpu;
-pub mod font;
+mod scheduler;
+mod config;
+mod options;
 mod sdl;
 mod frontend;
 
-use cpu::CPU;
-use frontend::Frontend;
+use config::Config;
+use chip8::coverage::Coverage;
+use chip8::cpu::CPU;
+use chip8::gdb::GdbStub;
+use chip8::monitor::Monitor;
+use chip8::profiler::Profiler;
+use chip8::tracer::Tracer;
+use frontend::{debugger::Debugger, display::DisplaySettings, Frontend};
+use chip8::machine::{Machine, TIMER_HZ};
+use options::Options;
+use scheduler::FrameScheduler;
+
+/// Host time spent running frames between draws in turbo mode.
+const TURBO_SLICE: Duration = Duration::from_millis(16);
 
 fn main() {
-    let mut fr = SDL2Frontend::new_frontend(sdl::SDLDisplayRenderer::Software, 20);
-
-    let file = File::open("sp_pong.ch8").expect("msg");
-    let mut c = CPU::new(false);
-    c.load_rom_in_ram(file);
-
-    loop {
-        let t0 = Instant::now();
-        let (chip8_keycode, pressed) = fr.input().input_loop();
-        // Received exit signal from input.
-        match (&chip8_keycode, pressed) {
-            (Some(Chip8KeyCode::Exit), true) => break,
-            (None, false) | (None, true) => {}
-            // Handle keycode in cpu
-            (Some(_), _) => {
-                let hex = <SDL2Frontend as Frontend>::Input::decode_input(chip8_keycode.unwrap());
-                c.keypress(hex, pressed)
+    let options = Options::parse();
+    let mut config = Config::load(options.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        process::exit(1);
+    });
+    options.apply(&mut config);
+    config.apply_rom(&options.rom);
+
+    // Debugging over GDB or in the console is headless, there is no need for SDL
+    if let Some(port) = options.gdb {
+        let mut stub = GdbStub::new(load_cpu(&options, &config));
+        stub.set_instructions_per_frame(config.machine.instructions_per_frame);
+        let result = TcpListener::bind(("127.0.0.1", port)).and_then(|listener| {
+            println!("Waiting for GDB on 127.0.0.1:{port}, connect with `target remote :{port}`");
+            let (stream, _) = listener.accept()?;
+            stub.serve(stream)
+        });
+        if let Err(e) = result {
+            eprintln!("gdb: {e}");
+        }
+        write_reports(&options, &mut stub.into_cpu());
+        return;
+    }
+    if options.debug {
+        let mut monitor = Monitor::new(load_cpu(&options, &config));
+        monitor.set_instructions_per_frame(config.machine.instructions_per_frame);
+        if let Err(e) = monitor.run(io::stdin().lock(), io::stdout()) {
+            eprintln!("debug: {e}");
+        }
+        write_reports(&options, &mut monitor.into_cpu());
+        return;
+    }
+
+    let settings = DisplaySettings {
+        render_scale: 20,
+        palette: config.display.palette(),
+        persistence: config.display.persistence,
+        effect: config.display.effect,
+        scaling: config.display.scaling,
+        fullscreen: config.display.fullscreen,
+        vsync: config.display.vsync,
+    };
+    let vsync = settings.vsync;
+    let mut fr = SDL2Frontend::new_frontend(
+        sdl::SDLDisplayRenderer::Software,
+        settings,
+        &config.keymap.keymap(),
+        &config.gamepad.button_map(),
+    );
+
+    if let Some(frames) = options.benchmark {
+        for (method, frame_time) in fr.display().benchmark(frames) {
+            println!("{method:?}: {:.3} ms per frame", frame_time.as_secs_f64() * 1000.0);
+        }
+        return;
+    }
+
+    let c = load_cpu(&options, &config);
+    let mut machine = Machine::new(c, config.machine.instructions_per_frame);
+    machine.set_timing(config.machine.timing);
+    machine.set_speed(config.machine.speed);
+    let mut turbo = config.machine.turbo;
+
+    let mut scheduler = FrameScheduler::new(TIMER_HZ);
+    let mut reported_speed = None;
+    let mut sound_timer = 0;
+    let mut debugger = Debugger::new();
+
+    'running: loop {
+        // Apply everything that happened since the last frame before running the CPU
+        for event in fr.input().input_loop() {
+            match event {
+                InputEvent::Quit => break 'running,
+                InputEvent::Key(key, pressed) => {
+                    let hex = <SDL2Frontend as Frontend>::Input::decode_input(key);
+                    machine.cpu_mut().keypress(hex, pressed)
+                }
+                InputEvent::NextEffect => {
+                    let effect = fr.display().effect().next();
+                    fr.display().set_effect(effect);
+                }
+                InputEvent::ToggleFullscreen => fr.display().toggle_fullscreen(),
+                InputEvent::SpeedDown => machine.slower(),
+                InputEvent::SpeedUp => machine.faster(),
+                InputEvent::ToggleTurbo => turbo = !turbo,
+                InputEvent::ToggleDebugger => {
+                    debugger.toggle();
+                    fr.set_overlay_visible(debugger.is_visible());
+                }
+                // Key up events go to the focused window, don't leave keys stuck down
+                InputEvent::FocusLost => {
+                    for key in 0..16 {
+                        machine.cpu_mut().keypress(key, false);
+                    }
+                }
+                // The display fits itself to the window on every draw
+                InputEvent::Resized(..) | InputEvent::FocusGained => {}
             }
         }
-        let vram = c.get_vram();
-        fr.display().draw(vram);
-        
-        for _ in 0..12 {
-            c.cycle();
+
+        if debugger.is_paused() {
+            // Frames come and go without running, only the debugger's steps do
+            scheduler.frames_due();
+            if let Err(e) = debugger.run_step(&mut machine) {
+                debugger.fault(e);
+            }
+            sound_timer = 0;
+        } else if turbo {
+            // Emulated frames still tick the timers once each, there are just more of them
+            let start = Instant::now();
+            let mut frames = 0;
+            while start.elapsed() < TURBO_SLICE {
+                sound_timer = match machine.run_frame() {
+                    Ok(sound_timer) => sound_timer,
+                    // With the debugger open a fault pauses, so it can be looked into
+                    Err(e) if debugger.is_visible() => {
+                        debugger.fault(e);
+                        break;
+                    }
+                    Err(e) => {
+                        eprintln!("{}: {e}", options.rom.display());
+                        break 'running;
+                    }
+                };
+                frames += 1;
+            }
+            scheduler.unthrottled(frames);
+        } else {
+            for _ in 0..scheduler.frames_due() {
+                sound_timer = match machine.run_frame() {
+                    Ok(sound_timer) => sound_timer,
+                    Err(e) if debugger.is_visible() => {
+                        debugger.fault(e);
+                        break;
+                    }
+                    Err(e) => {
+                        eprintln!("{}: {e}", options.rom.display());
+                        break 'running;
+                    }
+                };
+            }
+        }
+
+        if debugger.is_visible() {
+            // The debugger edits the machine while the frame is drawn
+            let vram = *machine.cpu().get_vram();
+            fr.draw_with_overlay(&vram, |ui| debugger.draw(ui, &mut machine));
+        } else {
+            fr.display().draw(machine.cpu().get_vram());
         }
-        
-        let sound_timer = c.cycle_timers();
 
         if sound_timer > 0 {
             fr.audio().start_beep();
@@ -51,8 +192,80 @@ fn main() {
             fr.audio().stop_beep();
         }
 
-        let elapsed = t0.elapsed().as_nanos();
+        if scheduler.speed() != reported_speed {
+            reported_speed = scheduler.speed();
+            let fps = reported_speed.unwrap_or_default();
+            let mode = if turbo { "turbo".to_owned() } else { format!("{}x", machine.speed()) };
+            fr.display().set_status(&format!(
+                "{mode}, {:.0}% ({fps:.1} fps, {} dropped)",
+                fps / TIMER_HZ as f64 * 100.0,
+                scheduler.dropped_frames()
+            ));
+        }
+
+        // With VSync presenting the frame already waited for the display
+        if !vsync && !turbo {
+            scheduler.wait();
+        }
+    }
+
+    write_reports(&options, machine.cpu_mut());
+}
+
+fn load_cpu(options: &Options, config: &Config) -> CPU {
+    let rom = fs::read(&options.rom).unwrap_or_else(|e| {
+        eprintln!("{}: {e}", options.rom.display());
+        process::exit(1);
+    });
+    let mut cpu = CPU::with_quirks(config.quirks.quirks());
+    cpu.load_rom(&rom);
+    if let Some(path) = &options.trace {
+        let output = File::create(path).unwrap_or_else(|e| {
+            eprintln!("{}: {e}", path.display());
+            process::exit(1);
+        });
+        let mut tracer = Tracer::new(BufWriter::new(output), options.trace_filter());
+        if let Some(count) = options.trace_last {
+            tracer = tracer.ring_buffer(count);
+        }
+        cpu.set_tracer(Some(tracer));
+    }
+    if options.profile.is_some() || options.profile_folded.is_some() {
+        cpu.set_profiler(Some(Profiler::new()));
+    }
+    if options.coverage.is_some() || options.coverage_map.is_some() {
+        cpu.set_coverage(Some(Coverage::new(rom.len())));
+    }
+    cpu
+}
+
+fn write_reports(options: &Options, cpu: &mut CPU) {
+    if let Some(profiler) = cpu.take_profiler() {
+        if let Some(path) = &options.profile {
+            write_file(path, |out| profiler.report(out));
+        }
+        if let Some(path) = &options.profile_folded {
+            write_file(path, |out| profiler.write_folded(out));
+        }
+    }
+    if let Some(coverage) = cpu.take_coverage() {
+        if let Some(path) = &options.coverage {
+            let title = options.rom.display().to_string();
+            write_file(path, |out| coverage.write_html(cpu, &title, out));
+        }
+        if let Some(path) = &options.coverage_map {
+            write_file(path, |out| coverage.write_heatmap(out));
+        }
+    }
+}
 
-        ::std::thread::sleep(Duration::new(0, ((1_000_000_000u128 - elapsed) / 60).try_into().unwrap()));
+fn write_file(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) {
+    let result = File::create(path).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        write(&mut out)?;
+        out.flush()
+    });
+    if let Err(e) = result {
+        eprintln!("{}: {e}", path.display());
     }
 }
@@ -0,0 +1,512 @@
+//! A line based debugger console for running ROMs without a display.
+//!
+//! Addresses and keys are hex, with or without `0x`. Other numbers are
+//! decimal unless they start with `0x`. An empty line repeats the last
+//! `step` or `continue`. The timers tick after every
+//! `instructions_per_frame` instructions rather than in real time.
+
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use crate::{
+    cpu::{CpuError, CpuState, CHIP8_WIDTH, CPU, KEY_COUNT, RAM},
+    disasm::Instruction,
+    machine::{DEFAULT_INSTRUCTIONS_PER_FRAME, TIMER_HZ},
+};
+
+/// How long `continue` runs without a limit, ten emulated seconds.
+const DEFAULT_CONTINUE_FRAMES: u64 = 10 * TIMER_HZ as u64;
+const DEFAULT_DISASSEMBLY: usize = 10;
+const DEFAULT_DUMP: usize = 64;
+
+const HELP: &str = "\
+step [N]           run N instructions (s)
+continue [FRAMES]  run until a breakpoint, a fault, a key wait or FRAMES frames (c)
+break [ADDR]       set a breakpoint, or list them without ADDR (b)
+delete ADDR        remove a breakpoint
+regs               show registers, stack and keys (r)
+mem ADDR [LEN]     dump memory
+dis [ADDR] [N]     disassemble N instructions, from PC without ADDR
+set REG VALUE      set V0-VF, I, PC, DT, ST or a byte of memory given its address
+press KEY          hold a key down
+release KEY        let go of a key
+screen             draw the display as text
+save FILE          write a save state
+load FILE          restore a save state
+quit               leave the debugger (q)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Register(u8),
+    Index,
+    ProgramCounter,
+    DelayTimer,
+    SoundTimer,
+    Memory(usize),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_uppercase().as_str() {
+            "I" => Target::Index,
+            "PC" => Target::ProgramCounter,
+            "DT" => Target::DelayTimer,
+            "ST" => Target::SoundTimer,
+            register if register.len() == 2 && register.starts_with('V') => {
+                Target::Register(u8::from_str_radix(&register[1..], 16).map_err(|_| format!("unknown register '{s}'"))?)
+            }
+            _ => Target::Memory(parse_address(s)?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Step(u64),
+    Continue(u64),
+    Break(Option<usize>),
+    Delete(usize),
+    Regs,
+    Mem(usize, usize),
+    Dis(Option<usize>, usize),
+    Set(Target, u16),
+    Press(u8, bool),
+    Screen,
+    Save(PathBuf),
+    Load(PathBuf),
+    Help,
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let arg = |n: usize| args.get(n).copied();
+        let missing = |what: &str| format!("{name}: missing {what}");
+
+        let command = match name {
+            "step" | "s" => Command::Step(arg(0).map(parse_number).transpose()?.unwrap_or(1)),
+            "continue" | "c" => {
+                Command::Continue(arg(0).map(parse_number).transpose()?.unwrap_or(DEFAULT_CONTINUE_FRAMES))
+            }
+            "break" | "b" => Command::Break(arg(0).map(parse_address).transpose()?),
+            "delete" => Command::Delete(parse_address(arg(0).ok_or_else(|| missing("address"))?)?),
+            "regs" | "r" => Command::Regs,
+            "mem" => Command::Mem(
+                parse_address(arg(0).ok_or_else(|| missing("address"))?)?,
+                arg(1).map(parse_number).transpose()?.unwrap_or(DEFAULT_DUMP as u64) as usize,
+            ),
+            "dis" => Command::Dis(
+                arg(0).map(parse_address).transpose()?,
+                arg(1).map(parse_number).transpose()?.unwrap_or(DEFAULT_DISASSEMBLY as u64) as usize,
+            ),
+            "set" => {
+                let target = arg(0).ok_or_else(|| missing("register"))?.parse()?;
+                let value = parse_number(arg(1).ok_or_else(|| missing("value"))?)?;
+                Command::Set(target, u16::try_from(value).map_err(|_| format!("{value} doesn't fit in 16 bits"))?)
+            }
+            "press" | "release" => Command::Press(parse_key(arg(0).ok_or_else(|| missing("key"))?)?, name == "press"),
+            "screen" => Command::Screen,
+            "save" => Command::Save(arg(0).ok_or_else(|| missing("file"))?.into()),
+            "load" => Command::Load(arg(0).ok_or_else(|| missing("file"))?.into()),
+            "help" | "h" | "?" => Command::Help,
+            "quit" | "q" => Command::Quit,
+            _ => return Err(format!("unknown command '{name}', try help")),
+        };
+        Ok(command)
+    }
+}
+
+fn parse_address(s: &str) -> Result<usize, String> {
+    match usize::from_str_radix(s.trim_start_matches("0x"), 16) {
+        Ok(address) if address < RAM => Ok(address),
+        _ => Err(format!("invalid address '{s}', expected hex 000-FFF")),
+    }
+}
+
+fn parse_number(s: &str) -> Result<u64, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("invalid number '{s}'"))
+}
+
+fn parse_key(s: &str) -> Result<u8, String> {
+    match u8::from_str_radix(s.trim_start_matches("0x"), 16) {
+        Ok(key) if (key as usize) < KEY_COUNT => Ok(key),
+        _ => Err(format!("invalid key '{s}', expected a hex digit 0-F")),
+    }
+}
+
+/// Why `step` or `continue` stopped early.
+enum Stop {
+    Breakpoint,
+    WaitingForKey,
+    Fault(CpuError),
+}
+
+/// Runs commands against a CPU, read from any input.
+#[derive(Debug)]
+pub struct Monitor {
+    cpu: CPU,
+    breakpoints: BTreeSet<usize>,
+    instructions_per_frame: u32,
+    // Instructions run since the timers last ticked
+    frame_instructions: u32,
+    last_run: Option<Command>,
+}
+
+impl Monitor {
+    pub fn new(cpu: CPU) -> Self {
+        Self {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            frame_instructions: 0,
+            last_run: None,
+        }
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_frame = instructions_per_frame.max(1);
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn into_cpu(self) -> CPU {
+        self.cpu
+    }
+
+    /// Prompts for and runs commands until `quit` or the end of `input`.
+    pub fn run(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "Type help for a list of commands.")?;
+        self.location(&mut out)?;
+
+        let mut lines = input.lines();
+        loop {
+            write!(out, "(chip8) ")?;
+            out.flush()?;
+            let Some(line) = lines.next().transpose()? else {
+                writeln!(out)?;
+                return Ok(());
+            };
+
+            let command = match line.trim() {
+                "" => match self.last_run.clone() {
+                    Some(command) => command,
+                    None => continue,
+                },
+                line => match line.parse() {
+                    Ok(command) => command,
+                    Err(e) => {
+                        writeln!(out, "{e}")?;
+                        continue;
+                    }
+                },
+            };
+            if command == Command::Quit {
+                return Ok(());
+            }
+            self.execute(command, &mut out)?;
+        }
+    }
+
+    fn execute(&mut self, command: Command, out: &mut impl Write) -> io::Result<()> {
+        match command {
+            Command::Step(count) => {
+                self.last_run = Some(command);
+                let stop = self.run_for(count, true);
+                self.report(stop, out)?;
+            }
+            Command::Continue(frames) => {
+                self.last_run = Some(command);
+                let stop = self.run_for(frames.saturating_mul(self.instructions_per_frame as u64), false);
+                self.report(stop, out)?;
+            }
+            Command::Break(None) => {
+                if self.breakpoints.is_empty() {
+                    writeln!(out, "no breakpoints")?;
+                }
+                for address in &self.breakpoints {
+                    writeln!(out, "{address:03X}")?;
+                }
+            }
+            Command::Break(Some(address)) => {
+                self.breakpoints.insert(address);
+            }
+            Command::Delete(address) => {
+                if !self.breakpoints.remove(&address) {
+                    writeln!(out, "no breakpoint at {address:03X}")?;
+                }
+            }
+            Command::Regs => self.registers(out)?,
+            Command::Mem(address, len) => self.dump(address, len, out)?,
+            Command::Dis(address, count) => {
+                self.disassemble(address.unwrap_or(self.cpu.program_counter()), count, out)?;
+            }
+            Command::Set(target, value) => {
+                if let Err(e) = self.set(target, value) {
+                    writeln!(out, "{e}")?;
+                }
+            }
+            Command::Press(key, pressed) => self.cpu.keypress(key as usize, pressed),
+            Command::Screen => self.screen(out)?,
+            Command::Save(path) => {
+                let result = File::create(&path).and_then(|file| {
+                    let mut writer = BufWriter::new(file);
+                    self.cpu.save_state(&mut writer)?;
+                    writer.flush()
+                });
+                if let Err(e) = result {
+                    writeln!(out, "{}: {e}", path.display())?;
+                }
+            }
+            Command::Load(path) => {
+                let result = File::open(&path).and_then(|file| self.cpu.load_state(&mut BufReader::new(file)));
+                match result {
+                    Ok(()) => self.location(out)?,
+                    Err(e) => writeln!(out, "{}: {e}", path.display())?,
+                }
+            }
+            Command::Help => writeln!(out, "{HELP}")?,
+            Command::Quit => {}
+        }
+        Ok(())
+    }
+
+    // Runs up to `count` instructions. Continuing stops at breakpoints and key
+    // waits, there's no one to press a key while it runs.
+    fn run_for(&mut self, count: u64, single_step: bool) -> Option<Stop> {
+        for executed in 0..count {
+            // The instruction under a breakpoint runs when continuing from it
+            if executed > 0 && self.breakpoints.contains(&self.cpu.program_counter()) {
+                return Some(Stop::Breakpoint);
+            }
+
+            if let Err(e) = self.cpu.cycle() {
+                return Some(Stop::Fault(e));
+            }
+            self.frame_instructions += 1;
+            if self.frame_instructions >= self.instructions_per_frame {
+                self.frame_instructions = 0;
+                self.cpu.cycle_timers();
+            }
+
+            if !single_step && matches!(self.cpu.state(), CpuState::WaitingForKey { .. }) {
+                return Some(Stop::WaitingForKey);
+            }
+        }
+        None
+    }
+
+    fn report(&self, stop: Option<Stop>, out: &mut impl Write) -> io::Result<()> {
+        match stop {
+            Some(Stop::Breakpoint) => writeln!(out, "breakpoint")?,
+            Some(Stop::WaitingForKey) => writeln!(out, "waiting for a key, use press")?,
+            Some(Stop::Fault(e)) => writeln!(out, "error: {e}")?,
+            None => {}
+        }
+        self.location(out)
+    }
+
+    fn location(&self, out: &mut impl Write) -> io::Result<()> {
+        self.disassemble(self.cpu.program_counter(), 1, out)
+    }
+
+    fn registers(&self, out: &mut impl Write) -> io::Result<()> {
+        for (row, values) in self.cpu.registers().chunks(8).enumerate() {
+            let line: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(column, value)| format!("V{:X} {value:02X}", row * 8 + column))
+                .collect();
+            writeln!(out, "{}", line.join("  "))?;
+        }
+        writeln!(
+            out,
+            "I  {:03X}  PC {:03X}  SP {:X}  DT {:02X}  ST {:02X}",
+            self.cpu.index_register(),
+            self.cpu.program_counter(),
+            self.cpu.stack().len(),
+            self.cpu.delay_timer(),
+            self.cpu.sound_timer()
+        )?;
+
+        let stack: String = self.cpu.stack().iter().map(|address| format!(" {address:03X}")).collect();
+        writeln!(out, "stack:{stack}")?;
+        let keys: String = (0..KEY_COUNT)
+            .filter(|&key| self.cpu.keys()[key])
+            .map(|key| format!(" {key:X}"))
+            .collect();
+        writeln!(out, "keys:{keys}")?;
+        if let CpuState::WaitingForKey { register, .. } = self.cpu.state() {
+            writeln!(out, "waiting for a key into V{register:X}")?;
+        }
+        Ok(())
+    }
+
+    fn dump(&self, address: usize, len: usize, out: &mut impl Write) -> io::Result<()> {
+        let end = address.saturating_add(len).min(RAM);
+        let memory = self.cpu.memory();
+        for start in (address..end).step_by(16) {
+            let row = &memory[start..(start + 16).min(end)];
+            let hex: Vec<String> = row.iter().map(|byte| format!("{byte:02X}")).collect();
+            let text: String = row
+                .iter()
+                .map(|&byte| if byte.is_ascii_graphic() { byte as char } else { '.' })
+                .collect();
+            writeln!(out, "{start:03X}  {:<47}  {text}", hex.join(" "))?;
+        }
+        Ok(())
+    }
+
+    fn disassemble(&self, address: usize, count: usize, out: &mut impl Write) -> io::Result<()> {
+        let memory = self.cpu.memory();
+        for address in (address..RAM - 1).step_by(2).take(count) {
+            let opcode = u16::from_be_bytes([memory[address], memory[address + 1]]);
+            let pc = if address == self.cpu.program_counter() { '>' } else { ' ' };
+            let breakpoint = if self.breakpoints.contains(&address) { '*' } else { ' ' };
+            writeln!(out, "{pc}{breakpoint}{address:03X}  {opcode:04X}  {}", Instruction::decode(opcode))?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, target: Target, value: u16) -> Result<(), String> {
+        let byte = || u8::try_from(value).map_err(|_| format!("{value:#X} doesn't fit in a byte"));
+        match target {
+            Target::Register(register) => self.cpu.registers_mut()[register as usize] = byte()?,
+            Target::Index => self.cpu.set_index_register(value),
+            Target::ProgramCounter if (value as usize) < RAM => self.cpu.set_program_counter(value as usize),
+            Target::ProgramCounter => return Err(format!("{value:#X} is outside of RAM")),
+            Target::DelayTimer => self.cpu.set_delay_timer(byte()?),
+            Target::SoundTimer => self.cpu.set_sound_timer(byte()?),
+            Target::Memory(address) => self.cpu.memory_mut()[address] = byte()?,
+        }
+        Ok(())
+    }
+
+    fn screen(&self, out: &mut impl Write) -> io::Result<()> {
+        let border = format!("+{}+", "-".repeat(CHIP8_WIDTH));
+        writeln!(out, "{border}")?;
+        for row in self.cpu.get_vram() {
+            let line: String = row.iter().map(|&pixel| if pixel != 0 { '#' } else { ' ' }).collect();
+            writeln!(out, "|{line}|")?;
+        }
+        writeln!(out, "{border}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Quirks;
+
+    // 200: V0 = 1, 202: V0 += 1, 204: jump 202
+    const LOOP: [u8; 6] = [0x60, 0x01, 0x70, 0x01, 0x12, 0x02];
+
+    // Runs `script` and returns what was printed after the banner
+    fn session(rom: &[u8], script: &str) -> (String, Monitor) {
+        let mut cpu = CPU::with_quirks(Quirks::default());
+        cpu.load_rom(rom);
+        let mut monitor = Monitor::new(cpu);
+        let mut out = Vec::new();
+        monitor.run(script.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let out = out.strip_prefix("Type help for a list of commands.\n").unwrap().to_owned();
+        (out, monitor)
+    }
+
+    #[test]
+    fn parses_commands() {
+        let parse = |line: &str| line.parse::<Command>();
+        assert_eq!(parse("s"), Ok(Command::Step(1)));
+        assert_eq!(parse("step 0x10"), Ok(Command::Step(16)));
+        assert_eq!(parse("c"), Ok(Command::Continue(DEFAULT_CONTINUE_FRAMES)));
+        assert_eq!(parse("b 0x20a"), Ok(Command::Break(Some(0x20A))));
+        assert_eq!(parse("mem 300"), Ok(Command::Mem(0x300, DEFAULT_DUMP)));
+        assert_eq!(parse("dis 200 4"), Ok(Command::Dis(Some(0x200), 4)));
+        assert_eq!(parse("set vA 0x2a"), Ok(Command::Set(Target::Register(0xA), 0x2A)));
+        assert_eq!(parse("set pc 512"), Ok(Command::Set(Target::ProgramCounter, 0x200)));
+        assert_eq!(parse("set 300 7"), Ok(Command::Set(Target::Memory(0x300), 7)));
+        assert_eq!(parse("release f"), Ok(Command::Press(0xF, false)));
+
+        assert_eq!(parse("mem"), Err("mem: missing address".to_owned()));
+        assert_eq!(parse("set v0"), Err("set: missing value".to_owned()));
+        assert!(parse("b 1000").is_err());
+        assert!(parse("press 10").is_err());
+        assert!(parse("set vg 1").is_err());
+        assert!(parse("set v0 0x10000").is_err());
+        assert_eq!(parse("jump"), Err("unknown command 'jump', try help".to_owned()));
+    }
+
+    #[test]
+    fn reports_bad_commands_and_carries_on() {
+        let (out, monitor) = session(&LOOP, "frobnicate\nset v0 0x100\ns\n");
+        assert!(out.contains("unknown command 'frobnicate', try help\n"), "{out}");
+        assert!(out.contains("0x100 doesn't fit in a byte\n"), "{out}");
+        assert_eq!(monitor.cpu().program_counter(), 0x202);
+    }
+
+    #[test]
+    fn continue_stops_at_breakpoints() {
+        let (out, monitor) = session(&LOOP, "b 204\nc\nc\nb\n");
+        assert_eq!(
+            out,
+            "\
+> 200  6001  LD V0, 0x01
+(chip8) (chip8) breakpoint
+>*204  1202  JP 0x202
+(chip8) breakpoint
+>*204  1202  JP 0x202
+(chip8) 204
+(chip8) \n"
+        );
+        // Continuing from the breakpoint ran the loop once more
+        assert_eq!(monitor.cpu().registers()[0], 3);
+    }
+
+    #[test]
+    fn continue_stops_on_a_key_wait() {
+        // 200: V1 = K, 202: jump 202
+        let rom = [0xF1, 0x0A, 0x12, 0x02];
+        let (out, monitor) = session(&rom, "c\npress 5\ns\nrelease 5\ns\n");
+        assert!(out.contains("waiting for a key, use press\n> 200  F10A  LD V1, K\n"), "{out}");
+        assert_eq!(monitor.cpu().registers()[1], 5);
+        assert_eq!(monitor.cpu().program_counter(), 0x202);
+    }
+
+    #[test]
+    fn empty_line_repeats_the_last_run() {
+        let (_, monitor) = session(&LOOP, "\ns\n\n\n");
+        // The first empty line had nothing to repeat
+        assert_eq!(monitor.cpu().program_counter(), 0x202);
+        assert_eq!(monitor.cpu().registers()[0], 2);
+
+        let (_, monitor) = session(&LOOP, "r\ns 2\n\n");
+        assert_eq!(monitor.cpu().registers()[0], 3);
+    }
+
+    #[test]
+    fn huge_counts_dont_overflow() {
+        // Returns with an empty stack
+        let (out, monitor) = session(&[0x00, 0xEE], "c 0xffffffffffffffff\nmem ff0 0xffffffffffffffff\n");
+        assert!(out.contains("error: "), "{out}");
+        assert_eq!(monitor.cpu().program_counter(), 0x200);
+        assert!(out.contains("FF0  00 00"), "{out}");
+        assert!(!out.contains("1000  "), "{out}");
+    }
+}
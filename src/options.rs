@@ -0,0 +1,211 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use chip8::{
+    cpu::QuirkPreset,
+    timing::Timing,
+    tracer::{AddressRange, FrameRange, OpcodeClasses, TraceFilter},
+};
+
+use crate::{
+    config::{Config, GamepadConfig, KeymapConfig, QuirksConfig},
+    frontend::{
+        crt::Effect,
+        display::Scaling,
+        gamepad::GamepadProfile,
+        keymap::KeymapPreset,
+        palette::{Palette, Rgb},
+    },
+};
+
+/// Mirai's Chip-8 interpreter
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Options {
+    /// ROM to load
+    #[arg(default_value = "sp_pong.ch8")]
+    pub rom: PathBuf,
+
+    /// Config file, defaults to chip8.toml in the working directory
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Colour palette: classic, amber, green, lcd or high-contrast
+    #[arg(long)]
+    pub palette: Option<Palette>,
+
+    /// Foreground colour, overrides the palette
+    #[arg(long, value_name = "RRGGBB")]
+    pub fg: Option<Rgb>,
+
+    /// Background colour, overrides the palette
+    #[arg(long, value_name = "RRGGBB")]
+    pub bg: Option<Rgb>,
+
+    /// Phosphor afterglow, the fraction of brightness kept every 1/60 s (0 to 0.95)
+    #[arg(long, value_name = "DECAY")]
+    pub persistence: Option<f32>,
+
+    /// Post-processing effect: none, scanlines, grid or crt. Cycle with F2
+    #[arg(long)]
+    pub effect: Option<Effect>,
+
+    /// How the screen is scaled to the window: integer or fractional
+    #[arg(long)]
+    pub scaling: Option<Scaling>,
+
+    /// Start in fullscreen, toggle with F11
+    #[arg(long)]
+    pub fullscreen: bool,
+
+    /// Wait for the display refresh when presenting frames
+    #[arg(long)]
+    pub vsync: bool,
+
+    /// Keyboard layout: qwerty, azerty or numpad
+    #[arg(long, value_name = "PRESET")]
+    pub keymap: Option<KeymapPreset>,
+
+    /// Gamepad mapping: keypad (d-pad on 2/4/6/8) or wasd (d-pad on 5/7/8/9)
+    #[arg(long, value_name = "PROFILE")]
+    pub gamepad: Option<GamepadProfile>,
+
+    /// CPU timing: ipf (fixed instructions per frame) or vip (COSMAC VIP cycle costs)
+    #[arg(long)]
+    pub timing: Option<Timing>,
+
+    /// Instructions executed per 60 Hz frame
+    #[arg(long, value_name = "N")]
+    pub ipf: Option<u32>,
+
+    /// Speed multiplier from 0.25 to 16, change with F3/F4
+    #[arg(long)]
+    pub speed: Option<f32>,
+
+    /// Run unthrottled, toggle with F5
+    #[arg(long)]
+    pub turbo: bool,
+
+    /// Interpreter quirks: chip8 (COSMAC VIP), schip or xochip
+    #[arg(long, value_name = "PRESET")]
+    pub quirks: Option<QuirkPreset>,
+
+    /// Time the renderer over the given number of frames and exit
+    #[arg(long, value_name = "FRAMES")]
+    pub benchmark: Option<u32>,
+
+    /// Log executed instructions to a file
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+
+    /// Only keep the last N traced instructions and write them out if the ROM crashes
+    #[arg(long, value_name = "N", requires = "trace")]
+    pub trace_last: Option<usize>,
+
+    /// Only trace instructions in this hex address range, e.g. 200-2FF
+    #[arg(long, value_name = "RANGE", requires = "trace")]
+    pub trace_addresses: Option<AddressRange>,
+
+    /// Only trace these opcode classes, by first hex digit, e.g. 8,D,F
+    #[arg(long, value_name = "CLASSES", requires = "trace")]
+    pub trace_ops: Option<OpcodeClasses>,
+
+    /// Only trace these frames, e.g. 100-200 or 100-
+    #[arg(long, value_name = "RANGE", requires = "trace")]
+    pub trace_frames: Option<FrameRange>,
+
+    /// Profile execution and write a report to a file on exit
+    #[arg(long, value_name = "FILE")]
+    pub profile: Option<PathBuf>,
+
+    /// Write the profile as folded stacks for flamegraph tools
+    #[arg(long, value_name = "FILE")]
+    pub profile_folded: Option<PathBuf>,
+
+    /// Track which bytes of RAM were executed, read and written, and write an HTML report on exit
+    #[arg(long, value_name = "FILE")]
+    pub coverage: Option<PathBuf>,
+
+    /// Write the RAM coverage as a PPM heatmap image
+    #[arg(long, value_name = "FILE")]
+    pub coverage_map: Option<PathBuf>,
+
+    /// Run without a window and wait for a GDB connection on 127.0.0.1:PORT
+    #[arg(long, value_name = "PORT")]
+    pub gdb: Option<u16>,
+
+    /// Run without a window in an interactive debugger console
+    #[arg(long, conflicts_with = "gdb")]
+    pub debug: bool,
+}
+
+impl Options {
+    pub fn trace_filter(&self) -> TraceFilter {
+        TraceFilter {
+            addresses: self.trace_addresses,
+            classes: self.trace_ops,
+            frames: self.trace_frames,
+        }
+    }
+
+    /// Command line options take precedence over the config file, but not over the
+    /// `[roms."<file name>"]` sections, which are applied after them.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(palette) = self.palette {
+            config.display.palette = Some(palette);
+            config.display.colors = None;
+        }
+        if let Some(fg) = self.fg {
+            config.display.foreground = Some(fg);
+        }
+        if let Some(bg) = self.bg {
+            config.display.background = Some(bg);
+        }
+        if let Some(persistence) = self.persistence {
+            config.display.persistence = persistence;
+        }
+        if let Some(effect) = self.effect {
+            config.display.effect = effect;
+        }
+        if let Some(scaling) = self.scaling {
+            config.display.scaling = scaling;
+        }
+        if self.fullscreen {
+            config.display.fullscreen = true;
+        }
+        if self.vsync {
+            config.display.vsync = true;
+        }
+        if let Some(preset) = self.keymap {
+            config.keymap.merge(KeymapConfig {
+                preset: Some(preset),
+                ..Default::default()
+            });
+        }
+        if let Some(timing) = self.timing {
+            config.machine.timing = timing;
+        }
+        if let Some(ipf) = self.ipf {
+            config.machine.instructions_per_frame = ipf;
+        }
+        if let Some(speed) = self.speed {
+            config.machine.speed = speed;
+        }
+        if self.turbo {
+            config.machine.turbo = true;
+        }
+        if let Some(preset) = self.quirks {
+            config.quirks.merge(QuirksConfig {
+                preset: Some(preset),
+                ..Default::default()
+            });
+        }
+        if let Some(profile) = self.gamepad {
+            config.gamepad.merge(GamepadConfig {
+                profile: Some(profile),
+                ..Default::default()
+            });
+        }
+    }
+}
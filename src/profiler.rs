@@ -0,0 +1,338 @@
+//! Execution profiling, to see where a ROM spends its instructions.
+//!
+//! Costs are counted both in instructions and in COSMAC VIP machine cycles,
+//! see `timing::vip_cycles`. Subroutines are tracked by following 2NNN and
+//! 00EE, and the resulting call stacks can be written as folded stacks for
+//! flamegraph tools.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use crate::{
+    cpu::{CPU, RAM},
+    disasm::Instruction,
+    timing::vip_cycles,
+};
+
+/// How many addresses the report lists.
+const HOTTEST: usize = 20;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Cost {
+    instructions: u64,
+    cycles: u64,
+}
+
+impl Cost {
+    fn add(&mut self, cycles: u32) {
+        self.instructions += 1;
+        self.cycles += cycles as u64;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Subroutine {
+    calls: u64,
+    // Everything that ran while it was on the call stack
+    inclusive: Cost,
+    // Only its own instructions
+    exclusive: Cost,
+}
+
+/// A loop polling the delay timer, found as a backward jump over an FX07.
+#[derive(Debug, Default)]
+struct BusyWait {
+    end: usize,
+    iterations: u64,
+    cost: Cost,
+}
+
+#[derive(Debug)]
+pub struct Profiler {
+    hits: Vec<u64>,
+    opcodes: Vec<u16>,
+    classes: [Cost; 16],
+    total: Cost,
+    frames: u64,
+    // Entry addresses of the subroutines being run, innermost last
+    call_stack: Vec<usize>,
+    subroutines: HashMap<usize, Subroutine>,
+    stacks: HashMap<Vec<usize>, u64>,
+    busy_waits: HashMap<usize, BusyWait>,
+    // Since the last backward jump
+    since_jump: Cost,
+    delay_timer_read: Option<usize>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            hits: vec![0; RAM],
+            opcodes: vec![0; RAM],
+            classes: [Cost::default(); 16],
+            total: Cost::default(),
+            frames: 0,
+            call_stack: Vec::new(),
+            subroutines: HashMap::new(),
+            stacks: HashMap::new(),
+            busy_waits: HashMap::new(),
+            since_jump: Cost::default(),
+            delay_timer_read: None,
+        }
+    }
+
+    /// Counts the instruction `cpu` is about to execute.
+    pub(crate) fn record(&mut self, cpu: &CPU) {
+        let pc = cpu.program_counter() % RAM;
+        let opcode = cpu.peek_opcode();
+        let cycles = vip_cycles(cpu, opcode);
+
+        self.hits[pc] += 1;
+        self.opcodes[pc] = opcode;
+        self.classes[(opcode >> 12) as usize].add(cycles);
+        self.total.add(cycles);
+        self.since_jump.add(cycles);
+
+        // Each routine counts once, however deeply it recursed
+        for (depth, &entry) in self.call_stack.iter().enumerate() {
+            if !self.call_stack[..depth].contains(&entry) {
+                self.subroutines.entry(entry).or_default().inclusive.add(cycles);
+            }
+        }
+        if let Some(&current) = self.call_stack.last() {
+            self.subroutines.entry(current).or_default().exclusive.add(cycles);
+        }
+        // The stack is only copied the first time it's seen
+        match self.stacks.get_mut(self.call_stack.as_slice()) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.call_stack.clone(), 1);
+            }
+        }
+
+        match Instruction::decode(opcode) {
+            Instruction::Call(nnn) => {
+                let entry = nnn as usize;
+                self.subroutines.entry(entry).or_default().calls += 1;
+                self.call_stack.push(entry);
+            }
+            Instruction::Ret => {
+                self.call_stack.pop();
+            }
+            Instruction::LdFromDt(_) => self.delay_timer_read = Some(pc),
+            Instruction::Jp(nnn) if nnn as usize <= pc => {
+                let start = nnn as usize;
+                if self.delay_timer_read.is_some_and(|read| (start..=pc).contains(&read)) {
+                    let wait = self.busy_waits.entry(start).or_default();
+                    wait.end = pc;
+                    wait.iterations += 1;
+                    wait.cost.instructions += self.since_jump.instructions;
+                    wait.cost.cycles += self.since_jump.cycles;
+                }
+                self.since_jump = Cost::default();
+                self.delay_timer_read = None;
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    /// Writes a human readable summary.
+    pub fn report(&self, out: &mut impl Write) -> io::Result<()> {
+        let total = self.total.instructions.max(1) as f64;
+        let percent = |count: u64| count as f64 / total * 100.0;
+
+        writeln!(
+            out,
+            "{} instructions, {} VIP cycles in {} frames ({:.1} instructions per frame)",
+            self.total.instructions,
+            self.total.cycles,
+            self.frames,
+            self.total.instructions as f64 / self.frames.max(1) as f64
+        )?;
+
+        writeln!(out, "\nOpcode classes\n  class  instructions      %      cycles")?;
+        for (class, cost) in self.classes.iter().enumerate() {
+            if cost.instructions > 0 {
+                writeln!(
+                    out,
+                    "  {class:X}xxx   {:>12} {:>6.2} {:>11}",
+                    cost.instructions,
+                    percent(cost.instructions),
+                    cost.cycles
+                )?;
+            }
+        }
+
+        let mut hottest: Vec<usize> = (0..RAM).filter(|&address| self.hits[address] > 0).collect();
+        hottest.sort_by_key(|&address| std::cmp::Reverse(self.hits[address]));
+        writeln!(out, "\nHottest addresses\n  addr          hits      %  instruction")?;
+        for &address in hottest.iter().take(HOTTEST) {
+            writeln!(
+                out,
+                "  {address:03X}   {:>12} {:>6.2}  {:04X} {}",
+                self.hits[address],
+                percent(self.hits[address]),
+                self.opcodes[address],
+                Instruction::decode(self.opcodes[address])
+            )?;
+        }
+
+        let mut subroutines: Vec<_> = self.subroutines.iter().collect();
+        subroutines.sort_by_key(|(_, subroutine)| std::cmp::Reverse(subroutine.inclusive.instructions));
+        writeln!(
+            out,
+            "\nSubroutines\n  entry     calls     inclusive      %          self      %   incl cycles   self cycles"
+        )?;
+        for (entry, subroutine) in subroutines {
+            writeln!(
+                out,
+                "  {entry:03X}  {:>10} {:>13} {:>6.2} {:>13} {:>6.2} {:>13} {:>13}",
+                subroutine.calls,
+                subroutine.inclusive.instructions,
+                percent(subroutine.inclusive.instructions),
+                subroutine.exclusive.instructions,
+                percent(subroutine.exclusive.instructions),
+                subroutine.inclusive.cycles,
+                subroutine.exclusive.cycles
+            )?;
+        }
+
+        let mut busy_waits: Vec<_> = self.busy_waits.iter().collect();
+        busy_waits.sort_by_key(|(_, wait)| std::cmp::Reverse(wait.cost.instructions));
+        writeln!(out, "\nDelay timer busy-waits\n  loop        iterations  instructions      %")?;
+        for (start, wait) in busy_waits {
+            writeln!(
+                out,
+                "  {start:03X}-{:03X}  {:>12} {:>13} {:>6.2}",
+                wait.end,
+                wait.iterations,
+                wait.cost.instructions,
+                percent(wait.cost.instructions)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes instruction counts per call stack, one `main;sub_2A4;sub_300 count` line each.
+    pub fn write_folded(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self
+            .stacks
+            .iter()
+            .map(|(stack, &count)| {
+                let frames: Vec<String> = stack.iter().map(|entry| format!("sub_{entry:03X}")).collect();
+                (["main".to_owned()].into_iter().chain(frames).collect::<Vec<_>>().join(";"), count)
+            })
+            .collect();
+        stacks.sort();
+
+        for (stack, count) in stacks {
+            writeln!(out, "{stack} {count}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Quirks;
+
+    fn profile(rom: &[u8], instructions: usize) -> Profiler {
+        let mut cpu = CPU::with_quirks(Quirks::default());
+        cpu.load_rom(rom);
+        cpu.set_profiler(Some(Profiler::new()));
+        for _ in 0..instructions {
+            cpu.cycle().unwrap();
+        }
+        cpu.take_profiler().unwrap()
+    }
+
+    // 200: call 206, 202: call 20C, 204: jump 200
+    // 206: V0 += 1, 208: call 20C, 20A: return
+    // 20C: V1 += 1, 20E: return
+    const CALLS: [u8; 16] = [
+        0x22, 0x06, 0x22, 0x0C, 0x12, 0x00, 0x70, 0x01, 0x22, 0x0C, 0x00, 0xEE, 0x71, 0x01, 0x00, 0xEE,
+    ];
+
+    #[test]
+    fn attributes_inclusive_and_exclusive_costs() {
+        // Up to the jump back to the start
+        let profiler = profile(&CALLS, 9);
+        assert_eq!(profiler.total.instructions, 9);
+
+        let outer = &profiler.subroutines[&0x206];
+        assert_eq!(outer.calls, 1);
+        // 206, 208, 20C, 20E, 20A
+        assert_eq!(outer.inclusive.instructions, 5);
+        assert_eq!(outer.exclusive.instructions, 3);
+
+        let inner = &profiler.subroutines[&0x20C];
+        assert_eq!(inner.calls, 2);
+        assert_eq!(inner.inclusive.instructions, 4);
+        assert_eq!(inner.exclusive.instructions, 4);
+        assert!(inner.inclusive.cycles > inner.inclusive.instructions);
+    }
+
+    #[test]
+    fn recursion_counts_once() {
+        // 200: call 200
+        let profiler = profile(&[0x22, 0x00], 4);
+        let subroutine = &profiler.subroutines[&0x200];
+        assert_eq!(subroutine.calls, 4);
+        // The first call ran in main
+        assert_eq!(subroutine.inclusive.instructions, 3);
+    }
+
+    #[test]
+    fn writes_folded_stacks() {
+        let profiler = profile(&CALLS, 9);
+        let mut out = Vec::new();
+        profiler.write_folded(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "main 2\nmain;sub_206 3\nmain;sub_206;sub_20C 2\nmain;sub_20C 2\n"
+        );
+    }
+
+    #[test]
+    fn finds_delay_timer_busy_waits() {
+        // 200: DT = V0, 202: V1 = DT, 204: skip if V1 == 0, 206: jump 202, 208: jump 208
+        let rom = [0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x02, 0x12, 0x08];
+        let mut cpu = CPU::with_quirks(Quirks::default());
+        cpu.load_rom(&rom);
+        cpu.registers_mut()[0] = 2;
+        cpu.set_profiler(Some(Profiler::new()));
+        for _ in 0..3 {
+            for _ in 0..12 {
+                cpu.cycle().unwrap();
+            }
+            cpu.cycle_timers();
+        }
+        let profiler = cpu.take_profiler().unwrap();
+
+        assert_eq!(profiler.busy_waits.len(), 1);
+        let wait = &profiler.busy_waits[&0x202];
+        assert_eq!(wait.end, 0x206);
+        // Polled until the timer ran out in the third frame
+        assert_eq!(wait.iterations, 8);
+        // Everything since the last backward jump, so the first pass includes 200
+        assert_eq!(wait.cost.instructions, 1 + 3 * 8);
+
+        let mut out = Vec::new();
+        profiler.report(&mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("\n  202-206             8            25"), "{report}");
+    }
+}
@@ -0,0 +1,222 @@
+use std::time::{Duration, Instant};
+
+/// Most frames run back to back to catch up after a stall. Anything beyond
+/// that is dropped, so a long hitch doesn't turn into a burst of fast-forward.
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+/// Where the scheduler reads the time from, so tests can drive it.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Paces emulated frames against the host clock.
+///
+/// Deadlines are accumulated from the start instead of measured from the end
+/// of the last frame, so time spent rendering or oversleeping doesn't make the
+/// emulation drift slower than the target rate.
+#[derive(Debug)]
+pub struct FrameScheduler<C: Clock = SystemClock> {
+    clock: C,
+    frame_time: Duration,
+    next_deadline: Instant,
+    dropped_frames: u64,
+    // Emulated frames counted since `window_start`, for measuring speed
+    window_start: Instant,
+    window_frames: u32,
+    speed: Option<f64>,
+}
+
+impl FrameScheduler {
+    pub fn new(rate_hz: u32) -> Self {
+        Self::with_clock(rate_hz, SystemClock)
+    }
+}
+
+impl<C: Clock> FrameScheduler<C> {
+    pub fn with_clock(rate_hz: u32, clock: C) -> Self {
+        let now = clock.now();
+        Self {
+            clock,
+            frame_time: Duration::from_secs(1) / rate_hz,
+            next_deadline: now,
+            dropped_frames: 0,
+            window_start: now,
+            window_frames: 0,
+            speed: None,
+        }
+    }
+
+    /// Returns how many emulated frames are due and schedules the next deadline.
+    pub fn frames_due(&mut self) -> u32 {
+        let now = self.clock.now();
+        let mut due = 0;
+        while now >= self.next_deadline && due < MAX_CATCH_UP_FRAMES {
+            self.next_deadline += self.frame_time;
+            due += 1;
+        }
+
+        if now >= self.next_deadline {
+            let behind = (now - self.next_deadline).as_nanos() / self.frame_time.as_nanos() + 1;
+            self.dropped_frames += behind as u64;
+            self.next_deadline = now + self.frame_time;
+        }
+
+        self.count_frames(now, due);
+        due
+    }
+
+    /// Records `frames` run without pacing, and paces from now on afterwards.
+    pub fn unthrottled(&mut self, frames: u32) {
+        let now = self.clock.now();
+        self.next_deadline = now;
+        self.count_frames(now, frames);
+    }
+
+    /// Sleeps until the next frame is due. Not needed when presenting with VSync,
+    /// which already blocks until the display refreshes.
+    pub fn wait(&self) {
+        if let Some(remaining) = self.next_deadline.checked_duration_since(self.clock.now()) {
+            std::thread::sleep(remaining);
+        }
+    }
+
+    /// Emulated frames per second over the last second, once measured.
+    pub fn speed(&self) -> Option<f64> {
+        self.speed
+    }
+
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    fn count_frames(&mut self, now: Instant, frames: u32) {
+        self.window_frames += frames;
+        let elapsed = now - self.window_start;
+        if elapsed >= Duration::from_secs(1) {
+            self.speed = Some(self.window_frames as f64 / elapsed.as_secs_f64());
+            self.window_start = now;
+            self.window_frames = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+    /// A clock that only moves when told to.
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<Instant>>);
+
+    impl FakeClock {
+        fn advance(&self, by: Duration) {
+            self.0.set(self.0.get() + by);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    fn scheduler() -> (FrameScheduler<FakeClock>, FakeClock) {
+        let clock = FakeClock(Rc::new(Cell::new(Instant::now())));
+        (FrameScheduler::with_clock(60, clock.clone()), clock)
+    }
+
+    #[test]
+    fn runs_one_frame_per_frame_time() {
+        let (mut scheduler, clock) = scheduler();
+        assert_eq!(scheduler.frames_due(), 1);
+        assert_eq!(scheduler.frames_due(), 0);
+
+        clock.advance(FRAME / 2);
+        assert_eq!(scheduler.frames_due(), 0);
+        clock.advance(FRAME / 2);
+        assert_eq!(scheduler.frames_due(), 1);
+        assert_eq!(scheduler.dropped_frames(), 0);
+    }
+
+    #[test]
+    fn deadlines_do_not_drift_with_late_polls() {
+        let (mut scheduler, clock) = scheduler();
+        scheduler.frames_due();
+
+        // Polled a little after every other deadline, the late part doesn't add up
+        let mut frames = 0;
+        for _ in 0..30 {
+            clock.advance(FRAME * 2);
+            frames += scheduler.frames_due();
+        }
+        assert_eq!(frames, 60);
+
+        clock.advance(FRAME / 2);
+        assert_eq!(scheduler.frames_due(), 0);
+        assert_eq!(scheduler.dropped_frames(), 0);
+    }
+
+    #[test]
+    fn catches_up_after_a_short_stall() {
+        let (mut scheduler, clock) = scheduler();
+        scheduler.frames_due();
+
+        clock.advance(FRAME * 3);
+        assert_eq!(scheduler.frames_due(), 3);
+        assert_eq!(scheduler.frames_due(), 0);
+        assert_eq!(scheduler.dropped_frames(), 0);
+    }
+
+    #[test]
+    fn drops_frames_after_a_long_stall() {
+        let (mut scheduler, clock) = scheduler();
+        scheduler.frames_due();
+
+        clock.advance(FRAME * 10);
+        assert_eq!(scheduler.frames_due(), MAX_CATCH_UP_FRAMES);
+        assert_eq!(scheduler.dropped_frames(), 10 - MAX_CATCH_UP_FRAMES as u64);
+
+        // Paced from now on instead of bursting through the backlog
+        assert_eq!(scheduler.frames_due(), 0);
+        clock.advance(FRAME);
+        assert_eq!(scheduler.frames_due(), 1);
+    }
+
+    #[test]
+    fn unthrottled_frames_reset_the_deadline() {
+        let (mut scheduler, clock) = scheduler();
+        clock.advance(FRAME * 10);
+        scheduler.unthrottled(100);
+        assert_eq!(scheduler.frames_due(), 1);
+        assert_eq!(scheduler.dropped_frames(), 0);
+    }
+
+    #[test]
+    fn measures_speed_over_a_second() {
+        let (mut scheduler, clock) = scheduler();
+        scheduler.frames_due();
+        for _ in 0..59 {
+            clock.advance(FRAME);
+            scheduler.frames_due();
+        }
+        assert_eq!(scheduler.speed(), None);
+
+        clock.advance(FRAME * 2);
+        scheduler.frames_due();
+        let speed = scheduler.speed().unwrap();
+        assert!((speed - 60.0).abs() < 1.5, "{speed}");
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet};
+
+use sdl2::{
+    controller::{Axis, Button, GameController},
+    event::Event,
+    GameControllerSubsystem, Sdl,
+};
+
+use crate::frontend::{
+    gamepad::ButtonMap,
+    input::{Chip8KeyCode, InputEvent},
+};
+
+// How far the stick has to be pushed before it counts as a d-pad press
+const STICK_DEADZONE: i16 = i16::MAX / 2;
+
+/// Gamepads opened through SDL's GameController API.
+///
+/// SDL reports every connected controller as added when the subsystem starts,
+/// so the same events handle both startup and hot-plugging.
+pub struct Controllers {
+    subsystem: GameControllerSubsystem,
+    // Keyed by joystick instance id
+    open: HashMap<u32, GameController>,
+    buttons: HashMap<Button, Chip8KeyCode>,
+    // Buttons held on each controller, released if it is unplugged
+    held: HashMap<u32, HashSet<Button>>,
+    // D-pad direction currently held by the left stick, per controller and axis
+    stick: HashMap<(u32, Axis), Button>,
+}
+
+impl Controllers {
+    pub fn from_context(sdl_context: &Sdl, button_map: &ButtonMap) -> Self {
+        let mut buttons = HashMap::new();
+        for (name, key) in button_map.bindings() {
+            match Button::from_string(name) {
+                Some(button) => {
+                    buttons.insert(button, key);
+                }
+                None => eprintln!("Unknown gamepad button '{name}', ignoring it"),
+            }
+        }
+
+        Self {
+            subsystem: sdl_context.game_controller().unwrap(),
+            open: HashMap::new(),
+            buttons,
+            held: HashMap::new(),
+            stick: HashMap::new(),
+        }
+    }
+
+    /// Handles gamepad events, returns `false` for any other event.
+    pub fn handle(&mut self, event: &Event, events: &mut Vec<InputEvent>) -> bool {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(which) {
+                Ok(controller) => {
+                    eprintln!("Gamepad connected: {}", controller.name());
+                    self.open.insert(controller.instance_id(), controller);
+                }
+                Err(e) => eprintln!("Could not open gamepad {which}: {e}"),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(controller) = self.open.remove(&which) {
+                    eprintln!("Gamepad disconnected: {}", controller.name());
+                }
+                self.release_all(events, which);
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.held.entry(which).or_default().insert(button);
+                self.push(events, button, true);
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                if let Some(held) = self.held.get_mut(&which) {
+                    held.remove(&button);
+                }
+                self.push(events, button, false);
+            }
+            Event::ControllerAxisMotion { which, axis: axis @ (Axis::LeftX | Axis::LeftY), value, .. } => {
+                self.stick_motion(events, which, axis, value)
+            }
+            Event::ControllerAxisMotion { .. } => {}
+            _ => return false,
+        }
+        true
+    }
+
+    fn push(&self, events: &mut Vec<InputEvent>, button: Button, pressed: bool) {
+        if let Some(&key) = self.buttons.get(&button) {
+            events.push(InputEvent::Key(key, pressed));
+        }
+    }
+
+    // Otherwise keys held on an unplugged controller would stay down
+    fn release_all(&mut self, events: &mut Vec<InputEvent>, which: u32) {
+        for button in self.held.remove(&which).unwrap_or_default() {
+            self.push(events, button, false);
+        }
+        for axis in [Axis::LeftX, Axis::LeftY] {
+            if let Some(direction) = self.stick.remove(&(which, axis)) {
+                self.push(events, direction, false);
+            }
+        }
+    }
+
+    fn stick_motion(&mut self, events: &mut Vec<InputEvent>, which: u32, axis: Axis, value: i16) {
+        let direction = match (axis, value) {
+            (Axis::LeftX, v) if v < -STICK_DEADZONE => Some(Button::DPadLeft),
+            (Axis::LeftX, v) if v > STICK_DEADZONE => Some(Button::DPadRight),
+            (Axis::LeftY, v) if v < -STICK_DEADZONE => Some(Button::DPadUp),
+            (Axis::LeftY, v) if v > STICK_DEADZONE => Some(Button::DPadDown),
+            _ => None,
+        };
+
+        let held = self.stick.get(&(which, axis)).copied();
+        if held == direction {
+            return;
+        }
+        if let Some(held) = held {
+            self.push(events, held, false);
+            self.stick.remove(&(which, axis));
+        }
+        if let Some(direction) = direction {
+            self.push(events, direction, true);
+            self.stick.insert((which, axis), direction);
+        }
+    }
+}
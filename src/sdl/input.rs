@@ -1,65 +1,113 @@
-use sdl2::{Sdl, EventPump, event::Event, keyboard::{Keycode, Scancode}};
+use std::collections::HashMap;
 
-use crate::frontend::input::{Chip8KeyCode, Input};
+use sdl2::{Sdl, EventPump, event::{Event, WindowEvent}, keyboard::Keycode};
+
+use crate::frontend::gamepad::ButtonMap;
+use crate::frontend::input::{Chip8KeyCode, Input, InputEvent};
+use crate::frontend::keymap::Keymap;
+
+use super::controller::Controllers;
 
 pub struct SDLInput {
     event_pump: EventPump,
+    keys: HashMap<Keycode, Chip8KeyCode>,
+    controllers: Controllers,
+    // Kept for the debugger overlay while it is shown
+    overlay_events: Option<Vec<Event>>,
+    // The overlay has a text field focused, keys don't go to the keypad
+    keyboard_captured: bool,
 }
 
 impl Input for SDLInput {
-    fn new() -> Self {
-        todo!()
-    }
+    fn input_loop(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        for event in self.event_pump.poll_iter() {
+            if let Some(overlay_events) = &mut self.overlay_events {
+                if is_overlay_event(&event) {
+                    overlay_events.push(event.clone());
+                }
+            }
+            if self.controllers.handle(&event, &mut events) {
+                continue;
+            }
 
-    fn input_loop(&mut self) -> (Option<Chip8KeyCode>, bool) {
-        if let Some(event) = self.event_pump.poll_event() {
-            return match event {
-                Event::Quit {..} |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => (Some(Chip8KeyCode::Exit), true),
-                Event::KeyDown { scancode: Some(Scancode::Num1), .. } => (Some(Chip8KeyCode::One), true),
-                Event::KeyDown { scancode: Some(Scancode::Num2), .. } => (Some(Chip8KeyCode::Two), true),
-                Event::KeyDown { scancode: Some(Scancode::Num3), .. } => (Some(Chip8KeyCode::Three), true),
-                Event::KeyDown { scancode: Some(Scancode::Num4), .. } => (Some(Chip8KeyCode::C), true),
-                Event::KeyDown { scancode: Some(Scancode::Q), .. } => (Some(Chip8KeyCode::Four), true),
-                Event::KeyDown { scancode: Some(Scancode::W), .. } => (Some(Chip8KeyCode::Five), true),
-                Event::KeyDown { scancode: Some(Scancode::E), .. } => (Some(Chip8KeyCode::Six), true),
-                Event::KeyDown { scancode: Some(Scancode::R), .. } => (Some(Chip8KeyCode::D), true),
-                Event::KeyDown { scancode: Some(Scancode::A), .. } => (Some(Chip8KeyCode::Seven), true),
-                Event::KeyDown { scancode: Some(Scancode::S), .. } => (Some(Chip8KeyCode::Eight), true),
-                Event::KeyDown { scancode: Some(Scancode::D), .. } => (Some(Chip8KeyCode::Nine), true),
-                Event::KeyDown { scancode: Some(Scancode::F), .. } => (Some(Chip8KeyCode::E), true),
-                Event::KeyDown { scancode: Some(Scancode::Z), .. } => (Some(Chip8KeyCode::A), true),
-                Event::KeyDown { scancode: Some(Scancode::X), .. } => (Some(Chip8KeyCode::Zero), true),
-                Event::KeyDown { scancode: Some(Scancode::C), .. } => (Some(Chip8KeyCode::B), true),
-                Event::KeyDown { scancode: Some(Scancode::V), .. } => (Some(Chip8KeyCode::F), true),
-                Event::KeyUp { keycode: Some(Keycode::Escape), .. } => (Some(Chip8KeyCode::Exit), false),
-                Event::KeyUp { scancode: Some(Scancode::Num1), .. } => (Some(Chip8KeyCode::One), false),
-                Event::KeyUp { scancode: Some(Scancode::Num2), .. } => (Some(Chip8KeyCode::Two), false),
-                Event::KeyUp { scancode: Some(Scancode::Num3), .. } => (Some(Chip8KeyCode::Three), false),
-                Event::KeyUp { scancode: Some(Scancode::Num4), .. } => (Some(Chip8KeyCode::C), false),
-                Event::KeyUp { scancode: Some(Scancode::Q), .. } => (Some(Chip8KeyCode::Four), false),
-                Event::KeyUp { scancode: Some(Scancode::W), .. } => (Some(Chip8KeyCode::Five), false),
-                Event::KeyUp { scancode: Some(Scancode::E), .. } => (Some(Chip8KeyCode::Six), false),
-                Event::KeyUp { scancode: Some(Scancode::R), .. } => (Some(Chip8KeyCode::D), false),
-                Event::KeyUp { scancode: Some(Scancode::A), .. } => (Some(Chip8KeyCode::Seven), false),
-                Event::KeyUp { scancode: Some(Scancode::S), .. } => (Some(Chip8KeyCode::Eight), false),
-                Event::KeyUp { scancode: Some(Scancode::D), .. } => (Some(Chip8KeyCode::Nine), false),
-                Event::KeyUp { scancode: Some(Scancode::F), .. } => (Some(Chip8KeyCode::E), false),
-                Event::KeyUp { scancode: Some(Scancode::Z), .. } => (Some(Chip8KeyCode::A), false),
-                Event::KeyUp { scancode: Some(Scancode::X), .. } => (Some(Chip8KeyCode::Zero), false),
-                Event::KeyUp { scancode: Some(Scancode::C), .. } => (Some(Chip8KeyCode::B), false),
-                Event::KeyUp { scancode: Some(Scancode::V), .. } => (Some(Chip8KeyCode::F), false),
-                _ => (None, false)
-            };
+            match event {
+                Event::Quit {..} => events.push(InputEvent::Quit),
+                // Keys bound in the keymap win over the hotkeys below. Releases still go
+                // through while typing, so a key held when typing started doesn't get stuck
+                Event::KeyDown { keycode: Some(keycode), .. } if self.keys.contains_key(&keycode) && self.keyboard_captured => {}
+                Event::KeyDown { keycode: Some(keycode), .. } if self.keys.contains_key(&keycode) => {
+                    events.push(InputEvent::Key(self.keys[&keycode], true));
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } if self.keys.contains_key(&keycode) => {
+                    events.push(InputEvent::Key(self.keys[&keycode], false));
+                }
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => events.push(InputEvent::Quit),
+                Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } => events.push(InputEvent::NextEffect),
+                Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => events.push(InputEvent::ToggleFullscreen),
+                Event::KeyDown { keycode: Some(Keycode::F3), repeat: false, .. } => events.push(InputEvent::SpeedDown),
+                Event::KeyDown { keycode: Some(Keycode::F4), repeat: false, .. } => events.push(InputEvent::SpeedUp),
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => events.push(InputEvent::ToggleTurbo),
+                Event::KeyDown { keycode: Some(Keycode::F1), repeat: false, .. } => events.push(InputEvent::ToggleDebugger),
+                Event::Window { win_event, .. } => match win_event {
+                    WindowEvent::SizeChanged(w, h) => events.push(InputEvent::Resized(w as u32, h as u32)),
+                    WindowEvent::FocusGained => events.push(InputEvent::FocusGained),
+                    WindowEvent::FocusLost => events.push(InputEvent::FocusLost),
+                    _ => {}
+                },
+                _ => {}
+            }
         }
-        (None, false)
+        events
     }
 }
 
 impl SDLInput {
-    pub fn from_context(sdl_context: &Sdl) -> Self {
+    pub fn from_context(sdl_context: &Sdl, keymap: &Keymap, buttons: &ButtonMap) -> Self {
+        let mut keys = HashMap::new();
+        for (name, key) in keymap.bindings() {
+            match Keycode::from_name(name) {
+                Some(keycode) => {
+                    keys.insert(keycode, key);
+                }
+                None => eprintln!("Unknown key '{name}' in keymap, ignoring it"),
+            }
+        }
+
         Self {
             event_pump: sdl_context.event_pump().unwrap(),
+            keys,
+            controllers: Controllers::from_context(sdl_context, buttons),
+            overlay_events: None,
+            keyboard_captured: false,
         }
     }
-}
\ No newline at end of file
+
+    /// Starts or stops keeping mouse and keyboard events for the overlay.
+    pub fn capture_overlay_events(&mut self, capture: bool) {
+        self.overlay_events = capture.then(Vec::new);
+        self.keyboard_captured = false;
+    }
+
+    /// Events for the overlay since the last call.
+    pub fn take_overlay_events(&mut self) -> Vec<Event> {
+        self.overlay_events.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn set_keyboard_captured(&mut self, captured: bool) {
+        self.keyboard_captured = captured;
+    }
+}
+
+fn is_overlay_event(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::MouseMotion { .. }
+            | Event::MouseButtonDown { .. }
+            | Event::MouseButtonUp { .. }
+            | Event::MouseWheel { .. }
+            | Event::TextInput { .. }
+            | Event::KeyDown { .. }
+            | Event::KeyUp { .. }
+    )
+}
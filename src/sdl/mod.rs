@@ -1,14 +1,17 @@
 #![allow(dead_code)]
 
 mod software;
+mod overlay;
 
 mod input;
+mod controller;
 mod audio;
-use core::panic;
 
+use chip8::cpu::{CHIP8_HEIGHT, CHIP8_WIDTH};
+use imgui::Ui;
 use input::SDLInput;
 
-use crate::frontend::Frontend;
+use crate::frontend::{display::DisplaySettings, gamepad::ButtonMap, keymap::Keymap, Frontend};
 
 use self::{software::SDL2SoftwareDisplay, audio::AudioBackend};
 
@@ -34,26 +37,22 @@ impl Frontend for SDL2Frontend {
         &mut self.display
     }
 
-    fn new(_render_scale: u32) -> Self {
-        panic!("Use the new_frontend() method");
-    }
-
     fn input(&mut self) -> &mut Self::Input {
         &mut self.input
     }
 }
 
 impl SDL2Frontend {
-    pub fn new_frontend(renderer: SDLDisplayRenderer, render_scale: u32) -> Self {
+    pub fn new_frontend(renderer: SDLDisplayRenderer, settings: DisplaySettings, keymap: &Keymap, buttons: &ButtonMap) -> Self {
         let sdl_context = sdl2::init().unwrap();
-        let input = SDLInput::from_context(&sdl_context);
+        let input = SDLInput::from_context(&sdl_context, keymap, buttons);
         let audio = AudioBackend::new(&sdl_context);
 
         let display = match renderer {
-            SDLDisplayRenderer::Software => SDL2SoftwareDisplay::from_context(&sdl_context, render_scale),
+            SDLDisplayRenderer::Software => SDL2SoftwareDisplay::from_context(&sdl_context, settings),
             _ => {
                 println!("{renderer:?} not available. Falling back to software rendering");
-                SDL2SoftwareDisplay::from_context(&sdl_context, render_scale)
+                SDL2SoftwareDisplay::from_context(&sdl_context, settings)
             } 
         };
         Self {
@@ -66,4 +65,16 @@ impl SDL2Frontend {
     pub fn audio(&self) -> &AudioBackend {
         &self.audio
     }
+
+    /// Shows or hides the debugger overlay, which takes the mouse and keyboard while shown.
+    pub fn set_overlay_visible(&mut self, visible: bool) {
+        self.input.capture_overlay_events(visible);
+    }
+
+    /// Draws `vram` with the debugger overlay laid out by `build` on top.
+    pub fn draw_with_overlay(&mut self, vram: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT], build: impl FnOnce(&Ui)) {
+        let events = self.input.take_overlay_events();
+        self.display.draw_with_overlay(vram, &events, build);
+        self.input.set_keyboard_captured(self.display.overlay_wants_keyboard());
+    }
 }
@@ -0,0 +1,197 @@
+//! Dear ImGui on top of the SDL canvas: feeds it SDL events and draws its
+//! triangles with `SDL_RenderGeometryRaw`, so no OpenGL context is needed.
+
+use std::mem::{offset_of, size_of};
+use std::os::raw::{c_float, c_int, c_void};
+use std::time::Instant;
+
+use imgui::{BackendFlags, DrawCmd, DrawVert, Key, MouseButton as ImguiButton, TextureId, Ui};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, Texture};
+use sdl2::sys::{SDL_Color, SDL_Renderer, SDL_Texture};
+use sdl2::video::Window;
+
+// Added in SDL 2.0.18, newer than the bindings in sdl2-sys
+extern "C" {
+    fn SDL_RenderGeometryRaw(
+        renderer: *mut SDL_Renderer,
+        texture: *mut SDL_Texture,
+        xy: *const c_float,
+        xy_stride: c_int,
+        color: *const SDL_Color,
+        color_stride: c_int,
+        uv: *const c_float,
+        uv_stride: c_int,
+        num_vertices: c_int,
+        indices: *const c_void,
+        num_indices: c_int,
+        size_indices: c_int,
+    ) -> c_int;
+}
+
+pub struct ImguiOverlay {
+    context: imgui::Context,
+    // Only the font atlas, every draw command uses it
+    font_texture: Texture,
+    last_frame: Instant,
+}
+
+impl ImguiOverlay {
+    pub fn new(canvas: &mut Canvas<Window>) -> Self {
+        let mut context = imgui::Context::create();
+        context.set_ini_filename(None);
+        context.io_mut().backend_flags.insert(BackendFlags::RENDERER_HAS_VTX_OFFSET);
+
+        let fonts = context.fonts();
+        let atlas = fonts.build_rgba32_texture();
+        let mut font_texture = canvas
+            .texture_creator()
+            .create_texture_static(PixelFormatEnum::ABGR8888, atlas.width, atlas.height)
+            .unwrap();
+        let _ = font_texture.update(None, atlas.data, atlas.width as usize * 4);
+        font_texture.set_blend_mode(BlendMode::Blend);
+        fonts.tex_id = TextureId::new(font_texture.raw() as usize);
+
+        Self {
+            context,
+            font_texture,
+            last_frame: Instant::now(),
+        }
+    }
+
+    /// True while a text field has focus, so typing doesn't also press keypad keys.
+    pub fn wants_keyboard(&self) -> bool {
+        self.context.io().want_capture_keyboard
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        let io = self.context.io_mut();
+        match *event {
+            Event::MouseMotion { x, y, .. } => io.add_mouse_pos_event([x as f32, y as f32]),
+            Event::MouseButtonDown { mouse_btn, .. } | Event::MouseButtonUp { mouse_btn, .. } => {
+                if let Some(button) = imgui_button(mouse_btn) {
+                    io.add_mouse_button_event(button, matches!(event, Event::MouseButtonDown { .. }));
+                }
+            }
+            Event::MouseWheel { x, y, .. } => io.add_mouse_wheel_event([x as f32, y as f32]),
+            Event::TextInput { ref text, .. } => text.chars().for_each(|c| io.add_input_character(c)),
+            Event::KeyDown { keycode: Some(keycode), keymod, .. } | Event::KeyUp { keycode: Some(keycode), keymod, .. } => {
+                let down = matches!(event, Event::KeyDown { .. });
+                io.add_key_event(Key::ModCtrl, keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD));
+                io.add_key_event(Key::ModShift, keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD));
+                io.add_key_event(Key::ModAlt, keymod.intersects(Mod::LALTMOD | Mod::RALTMOD));
+                if let Some(key) = imgui_key(keycode) {
+                    io.add_key_event(key, down);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Lays out a frame with `build` and draws it over whatever is on the canvas.
+    pub fn render(&mut self, canvas: &mut Canvas<Window>, build: impl FnOnce(&Ui)) {
+        let (width, height) = canvas.window().size();
+        let (output_width, output_height) = canvas.output_size().unwrap_or((width, height));
+        let now = Instant::now();
+        let io = self.context.io_mut();
+        io.display_size = [width as f32, height as f32];
+        io.display_framebuffer_scale = [
+            output_width as f32 / width.max(1) as f32,
+            output_height as f32 / height.max(1) as f32,
+        ];
+        io.delta_time = (now - self.last_frame).as_secs_f32().max(f32::EPSILON);
+        self.last_frame = now;
+
+        build(self.context.new_frame());
+        let draw_data = self.context.render();
+
+        // ImGui works in window coordinates, SDL scales them up on high DPI displays
+        let (scale_x, scale_y) = canvas.scale();
+        let _ = canvas.set_scale(draw_data.framebuffer_scale[0], draw_data.framebuffer_scale[1]);
+
+        let [left, top] = draw_data.display_pos;
+        for draw_list in draw_data.draw_lists() {
+            let vertices = draw_list.vtx_buffer();
+            let indices = draw_list.idx_buffer();
+            for command in draw_list.commands() {
+                let DrawCmd::Elements { count, cmd_params } = command else {
+                    continue;
+                };
+                let [x1, y1, x2, y2] = cmd_params.clip_rect;
+                if x2 <= x1 || y2 <= y1 {
+                    continue;
+                }
+                canvas.set_clip_rect(Rect::new(
+                    (x1 - left) as i32,
+                    (y1 - top) as i32,
+                    (x2 - x1) as u32,
+                    (y2 - y1) as u32,
+                ));
+
+                let vertices = &vertices[cmd_params.vtx_offset..];
+                let indices = &indices[cmd_params.idx_offset..cmd_params.idx_offset + count];
+                let base = vertices.as_ptr() as *const u8;
+                let stride = size_of::<DrawVert>() as c_int;
+                // SAFETY: the pointers and strides describe the vertex buffer, which
+                // outlives the call, and every index is within it
+                unsafe {
+                    SDL_RenderGeometryRaw(
+                        canvas.raw(),
+                        cmd_params.texture_id.id() as *mut SDL_Texture,
+                        base.add(offset_of!(DrawVert, pos)) as *const c_float,
+                        stride,
+                        base.add(offset_of!(DrawVert, col)) as *const SDL_Color,
+                        stride,
+                        base.add(offset_of!(DrawVert, uv)) as *const c_float,
+                        stride,
+                        vertices.len() as c_int,
+                        indices.as_ptr() as *const c_void,
+                        count as c_int,
+                        size_of::<imgui::DrawIdx>() as c_int,
+                    );
+                }
+            }
+        }
+
+        canvas.set_clip_rect(None);
+        let _ = canvas.set_scale(scale_x, scale_y);
+    }
+}
+
+fn imgui_button(button: MouseButton) -> Option<ImguiButton> {
+    match button {
+        MouseButton::Left => Some(ImguiButton::Left),
+        MouseButton::Right => Some(ImguiButton::Right),
+        MouseButton::Middle => Some(ImguiButton::Middle),
+        _ => None,
+    }
+}
+
+// Only what text fields and scrolling need, letters are for the copy and paste shortcuts
+fn imgui_key(keycode: Keycode) -> Option<Key> {
+    Some(match keycode {
+        Keycode::Tab => Key::Tab,
+        Keycode::Left => Key::LeftArrow,
+        Keycode::Right => Key::RightArrow,
+        Keycode::Up => Key::UpArrow,
+        Keycode::Down => Key::DownArrow,
+        Keycode::PageUp => Key::PageUp,
+        Keycode::PageDown => Key::PageDown,
+        Keycode::Home => Key::Home,
+        Keycode::End => Key::End,
+        Keycode::Delete => Key::Delete,
+        Keycode::Backspace => Key::Backspace,
+        Keycode::Return => Key::Enter,
+        Keycode::KpEnter => Key::KeypadEnter,
+        Keycode::A => Key::A,
+        Keycode::C => Key::C,
+        Keycode::V => Key::V,
+        Keycode::X => Key::X,
+        Keycode::Z => Key::Z,
+        _ => return None,
+    })
+}
@@ -1,61 +1,270 @@
+use std::time::{Duration, Instant};
+
+use imgui::Ui;
 use sdl2::Sdl;
-use sdl2::pixels::Color;
+use sdl2::event::Event;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
+
+use crate::frontend::crt::{CrtFilter, Effect};
+use crate::frontend::display::{Display, DisplaySettings, Frame, Scaling};
+use crate::frontend::palette::{Palette, Rgb};
+use crate::frontend::phosphor::PhosphorFilter;
+
+use super::overlay::ImguiOverlay;
 
-use crate::frontend::display::Display;
+const TITLE: &str = "Mirai's Chip-8 interpreter (Software)";
 
 pub struct SDL2SoftwareDisplay {
     canvas: Canvas<Window>,
-    render_scale: u32,
+    palette: Palette,
+    scaling: Scaling,
+    frame: Frame,
+    phosphor: Option<PhosphorFilter>,
+    crt: CrtFilter,
+    // The frame at its native size, scaled up by SDL
+    texture: Option<Texture>,
+    // Target of the post-processing pipeline, created on first use
+    effects_texture: Option<Texture>,
+    // Created the first time the debugger is shown
+    overlay: Option<ImguiOverlay>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawMethod {
+    /// One `fill_rect` per pixel, the original renderer
+    Rects,
+    /// A single streaming texture scaled by SDL
+    Texture,
 }
 
-use crate::cpu::{CHIP8_HEIGHT, CHIP8_WIDTH};
+use chip8::cpu::{CHIP8_HEIGHT, CHIP8_WIDTH};
 
 impl Display for SDL2SoftwareDisplay {
     fn draw(&mut self, vram: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
-        self.canvas.clear();
-
-        for y in vram.iter().enumerate() {
-            let y_coord = y.0 as u32 * self.render_scale;
-            for (x, &row) in y.1.iter().enumerate() {
-                let x_coord = x as u32 * self.render_scale;
-                let mut output_color = Color::RGB(0, 0, 0);
-
-                if row == 1 {
-                    output_color = Color::RGB(255, 255, 255);
-                }
-
-                self.canvas.set_draw_color(output_color);
-
-                let _ = self.canvas.fill_rect(Rect::new(x_coord as i32, y_coord as i32, self.render_scale, self.render_scale));
-            }
-        }
-
+        self.render(vram);
         self.canvas.present();
     }
 }
 
 impl SDL2SoftwareDisplay {
-    pub fn from_context(sdl_context: &Sdl, render_scale: u32) -> Self {
+    pub fn from_context(sdl_context: &Sdl, settings: DisplaySettings) -> Self {
         let video_subsystem = sdl_context.video().unwrap();
+        let render_scale = settings.render_scale;
 
-        let window = video_subsystem
-            .window("Mirai's Chip-8 interpreter (Software)", CHIP8_WIDTH as u32 * render_scale, CHIP8_HEIGHT as u32 * render_scale)
-            //.position_centered()
-            .position(-100, 0)
+        // Keep pixels sharp when SDL scales the texture
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+
+        let mut window = video_subsystem
+            .window(TITLE, CHIP8_WIDTH as u32 * render_scale, CHIP8_HEIGHT as u32 * render_scale)
+            .position_centered()
+            .resizable()
             .build()
             .unwrap();
+        let _ = window.set_minimum_size(CHIP8_WIDTH as u32, CHIP8_HEIGHT as u32);
+
+        let mut canvas = window.into_canvas();
+        if settings.vsync {
+            canvas = canvas.present_vsync();
+        }
 
         let mut d = SDL2SoftwareDisplay {
-            canvas: window.into_canvas().build().unwrap(),
-            render_scale
+            canvas: canvas.build().unwrap(),
+            palette: settings.palette,
+            scaling: settings.scaling,
+            frame: Frame::new(CHIP8_WIDTH, CHIP8_HEIGHT),
+            phosphor: (settings.persistence > 0.0).then(|| PhosphorFilter::new(settings.persistence)),
+            crt: CrtFilter::new(settings.effect, render_scale as usize),
+            texture: None,
+            effects_texture: None,
+            overlay: None,
         };
 
+        if settings.fullscreen {
+            d.toggle_fullscreen();
+        }
+
         d.canvas.set_draw_color(Color::RGB(0, 0, 0));
         d.canvas.clear();
         d.canvas.present();
         d
     }
-}
\ No newline at end of file
+
+    pub fn effect(&self) -> Effect {
+        self.crt.effect()
+    }
+
+    pub fn set_effect(&mut self, effect: Effect) {
+        self.crt.set_effect(effect);
+    }
+
+    /// Draws `vram` with an ImGui overlay laid out by `build` on top.
+    pub fn draw_with_overlay(
+        &mut self,
+        vram: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
+        events: &[Event],
+        build: impl FnOnce(&Ui),
+    ) {
+        self.render(vram);
+        let overlay = self.overlay.get_or_insert_with(|| ImguiOverlay::new(&mut self.canvas));
+        for event in events {
+            overlay.handle_event(event);
+        }
+        overlay.render(&mut self.canvas, build);
+        self.canvas.present();
+    }
+
+    /// True while the overlay has a text field focused.
+    pub fn overlay_wants_keyboard(&self) -> bool {
+        self.overlay.as_ref().is_some_and(ImguiOverlay::wants_keyboard)
+    }
+
+    /// Shows `status` in the window title.
+    pub fn set_status(&mut self, status: &str) {
+        let _ = self.canvas.window_mut().set_title(&format!("{TITLE} - {status}"));
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let state = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(e) = window.set_fullscreen(state) {
+            eprintln!("Could not toggle fullscreen: {e}");
+        }
+    }
+
+    /// Draws a test pattern `frames` times with each method and returns the average frame time.
+    pub fn benchmark(&mut self, frames: u32) -> Vec<(DrawMethod, Duration)> {
+        let mut vram = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        let frames = frames.max(1);
+        let viewport = self.viewport();
+
+        [DrawMethod::Rects, DrawMethod::Texture]
+            .into_iter()
+            .map(|method| {
+                let start = Instant::now();
+                for i in 0..frames as usize {
+                    // Checkerboard shifted each frame so every pixel changes
+                    for (y, row) in vram.iter_mut().enumerate() {
+                        for (x, pixel) in row.iter_mut().enumerate() {
+                            *pixel = ((x + y + i) % 2) as u8;
+                        }
+                    }
+                    self.frame.render(&vram, &self.palette);
+                    self.canvas.clear();
+                    match method {
+                        DrawMethod::Rects => self.draw_rects(viewport),
+                        DrawMethod::Texture => self.draw_texture(viewport),
+                    }
+                    self.canvas.present();
+                }
+                (method, start.elapsed() / frames)
+            })
+            .collect()
+    }
+
+    // Everything but presenting, so an overlay can be drawn on top
+    fn render(&mut self, vram: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
+        if self.frame.render(vram, &self.palette) {
+            // Low-res/hi-res switch, the textures no longer match the frame
+            self.destroy_textures();
+        }
+
+        if let Some(phosphor) = &mut self.phosphor {
+            phosphor.apply(&mut self.frame.pixels, self.palette.background());
+        }
+
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+
+        let viewport = self.viewport();
+        if self.crt.effect() == Effect::None {
+            self.draw_texture(viewport);
+        } else {
+            self.draw_effects(viewport);
+        }
+    }
+
+    // Where the frame goes in the window, recomputed every frame to follow resizes
+    fn viewport(&self) -> Rect {
+        let (output_width, output_height) = self.canvas.output_size().unwrap();
+        let (x, y, w, h) = self.scaling.fit(
+            self.frame.width as u32,
+            self.frame.height as u32,
+            output_width,
+            output_height,
+        );
+        Rect::new(x, y, w, h)
+    }
+
+    fn destroy_textures(&mut self) {
+        for texture in [self.texture.take(), self.effects_texture.take()].into_iter().flatten() {
+            // SAFETY: the canvas that created the texture is still alive
+            unsafe { texture.destroy() };
+        }
+    }
+
+    fn draw_texture(&mut self, viewport: Rect) {
+        let frame = &self.frame;
+        let texture = self.texture.get_or_insert_with(|| {
+            self.canvas
+                .texture_creator()
+                .create_texture_streaming(PixelFormatEnum::RGB24, frame.width as u32, frame.height as u32)
+                .unwrap()
+        });
+
+        let _ = texture.with_lock(None, |buffer, pitch| {
+            for (row, pixels) in buffer.chunks_exact_mut(pitch).zip(frame.pixels.chunks_exact(frame.width)) {
+                for (out, &Rgb(r, g, b)) in row.chunks_exact_mut(3).zip(pixels) {
+                    out.copy_from_slice(&[r, g, b]);
+                }
+            }
+        });
+        let _ = self.canvas.copy(texture, None, viewport);
+    }
+
+    fn draw_rects(&mut self, viewport: Rect) {
+        let scale = (viewport.height() / self.frame.height as u32).max(1);
+        for (y, row) in self.frame.pixels.chunks_exact(self.frame.width).enumerate() {
+            let y_coord = viewport.y() + (y as u32 * scale) as i32;
+            for (x, &pixel) in row.iter().enumerate() {
+                let x_coord = viewport.x() + (x as u32 * scale) as i32;
+                self.canvas.set_draw_color(to_color(pixel));
+
+                let _ = self.canvas.fill_rect(Rect::new(x_coord, y_coord, scale, scale));
+            }
+        }
+    }
+
+    fn draw_effects(&mut self, viewport: Rect) {
+        // Process at the displayed size so scanlines line up with the screen pixels
+        let scale = (viewport.height() as usize / self.frame.height).max(1);
+        if scale != self.crt.scale() {
+            self.crt.set_scale(scale);
+            if let Some(texture) = self.effects_texture.take() {
+                // SAFETY: the canvas that created the texture is still alive
+                unsafe { texture.destroy() };
+            }
+        }
+
+        let (width, height) = self.crt.output_size(&self.frame);
+        let texture = self.effects_texture.get_or_insert_with(|| {
+            self.canvas
+                .texture_creator()
+                .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+                .unwrap()
+        });
+
+        let pixels = self.crt.process(&self.frame);
+        let _ = texture.update(None, pixels, width * 3);
+        let _ = self.canvas.copy(texture, None, viewport);
+    }
+}
+
+fn to_color(rgb: Rgb) -> Color {
+    Color::RGB(rgb.0, rgb.1, rgb.2)
+}
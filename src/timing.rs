@@ -0,0 +1,136 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::cpu::CPU;
+
+/// Machine cycles (8 clocks of the 1.76 MHz CDP1802) in one 60 Hz frame of the COSMAC VIP.
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+/// Cycles per frame taken by display DMA (8 bytes for each of 128 scanlines)
+/// and the display interrupt routine, which leaves the rest for the interpreter.
+pub const VIP_DISPLAY_CYCLES: u32 = 1024 + 46;
+
+// Fetching and decoding an instruction in the interpreter's main loop
+const VIP_FETCH_CYCLES: u32 = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Timing {
+    /// A fixed number of instructions per frame
+    #[default]
+    Instructions,
+    /// Each instruction costs what it did on the COSMAC VIP, within a fixed cycle budget per frame.
+    /// Draws wait for the display interrupt, whatever the display wait quirk says.
+    Vip,
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "instructions" | "ipf" => Ok(Timing::Instructions),
+            "vip" => Ok(Timing::Vip),
+            _ => Err(format!("unknown timing '{s}', expected ipf or vip")),
+        }
+    }
+}
+
+impl TryFrom<String> for Timing {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Approximate cost in machine cycles of the VIP interpreter executing `opcode`
+/// with the CPU in its current state, including fetch and decode.
+pub fn vip_cycles(cpu: &CPU, opcode: u16) -> u32 {
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+    let n = (opcode & 0x000F) as u32;
+    let vx = cpu.registers()[x] as u32;
+
+    let execute = match opcode & 0xF000 {
+        0x0000 => match opcode {
+            // Clears all 256 bytes of display memory
+            0x00E0 => 24 + 3078,
+            0x00EE => 10,
+            // Machine language subroutine, unknown cost
+            _ => 0,
+        },
+        0x1000 => 12,
+        0x2000 => 26,
+        0x3000 | 0x4000 => 14,
+        0x5000 | 0x9000 => 18,
+        0x6000 => 6,
+        0x7000 => 10,
+        0x8000 => 44,
+        0xA000 => 12,
+        0xB000 => 22,
+        0xC000 => 36,
+        // Each sprite row is shifted into place bit by bit, so unaligned sprites cost more
+        0xD000 => 68 + n * (46 + 16 * (vx % 8)),
+        0xE000 => 18,
+        0xF000 => match opcode & 0x00FF {
+            // BCD conversion subtracts repeatedly, once per unit of each digit
+            0x33 => 80 + 16 * (vx / 100 + vx / 10 % 10 + vx % 10),
+            0x55 | 0x65 => 14 + 14 * (x as u32 + 1),
+            0x0A => 18,
+            0x1E | 0x29 => 16,
+            _ => 10,
+        },
+        _ => 0,
+    };
+
+    VIP_FETCH_CYCLES + execute
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Quirks;
+
+    fn cpu_with(registers: &[(usize, u8)]) -> CPU {
+        let mut cpu = CPU::with_quirks(Quirks::default());
+        for &(register, value) in registers {
+            cpu.registers_mut()[register] = value;
+        }
+        cpu
+    }
+
+    #[test]
+    fn parses_timing() {
+        assert_eq!("ipf".parse(), Ok(Timing::Instructions));
+        assert_eq!("VIP".parse(), Ok(Timing::Vip));
+        assert!("cycles".parse::<Timing>().is_err());
+    }
+
+    #[test]
+    fn every_instruction_pays_for_fetch() {
+        let cpu = cpu_with(&[]);
+        assert_eq!(vip_cycles(&cpu, 0x6001), VIP_FETCH_CYCLES + 6);
+        assert_eq!(vip_cycles(&cpu, 0x0123), VIP_FETCH_CYCLES);
+        assert_eq!(vip_cycles(&cpu, 0x00E0), VIP_FETCH_CYCLES + 24 + 3078);
+    }
+
+    #[test]
+    fn unaligned_sprites_cost_more() {
+        let aligned = vip_cycles(&cpu_with(&[(1, 8)]), 0xD125);
+        let unaligned = vip_cycles(&cpu_with(&[(1, 11)]), 0xD125);
+        assert_eq!(aligned, VIP_FETCH_CYCLES + 68 + 5 * 46);
+        assert_eq!(unaligned, aligned + 5 * 16 * 3);
+        // Rows are what costs
+        assert!(vip_cycles(&cpu_with(&[]), 0xD12F) > vip_cycles(&cpu_with(&[]), 0xD121));
+    }
+
+    #[test]
+    fn costs_depend_on_operands() {
+        // One subtraction per unit of each digit of 255
+        assert_eq!(vip_cycles(&cpu_with(&[(0, 255)]), 0xF033), VIP_FETCH_CYCLES + 80 + 16 * 12);
+        assert_eq!(vip_cycles(&cpu_with(&[(0, 0)]), 0xF033), VIP_FETCH_CYCLES + 80);
+        assert_eq!(vip_cycles(&cpu_with(&[]), 0xF055), VIP_FETCH_CYCLES + 28);
+        assert_eq!(vip_cycles(&cpu_with(&[]), 0xFF65), VIP_FETCH_CYCLES + 14 + 14 * 16);
+    }
+}
@@ -0,0 +1,254 @@
+//! Per-instruction CPU state traces, for comparing against other interpreters.
+//!
+//! A trace is a text file with one record per executed instruction, giving the
+//! state *before* the instruction ran:
+//!
+//! ```text
+//! PC:0200 OP:00E0 V:00,00,00,00,00,00,00,00,00,00,00,00,00,00,00,00 I:0000 DT:00 ST:00
+//! ```
+//!
+//! All values are hex. Blank lines and lines starting with `#` are ignored.
+//! Logs from other interpreters can be read with `parse_key_values` instead.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use crate::cpu::{CpuError, CPU};
+
+/// How many matching records are shown before a divergence.
+const CONTEXT: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: [u8; 16],
+    pub index: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceRecord {
+    /// The state of `cpu` about to execute its next instruction.
+    pub fn capture(cpu: &CPU) -> Self {
+        Self {
+            pc: cpu.program_counter() as u16,
+            opcode: cpu.peek_opcode(),
+            registers: *cpu.registers(),
+            index: cpu.index_register(),
+            delay_timer: cpu.delay_timer(),
+            sound_timer: cpu.sound_timer(),
+        }
+    }
+
+    /// Names of the fields that differ between the two records.
+    pub fn differences(&self, other: &TraceRecord) -> Vec<String> {
+        let mut fields = Vec::new();
+        if self.pc != other.pc {
+            fields.push("PC".to_owned());
+        }
+        if self.opcode != other.opcode {
+            fields.push("OP".to_owned());
+        }
+        for (register, (a, b)) in self.registers.iter().zip(other.registers).enumerate() {
+            if *a != b {
+                fields.push(format!("V{register:X}"));
+            }
+        }
+        if self.index != other.index {
+            fields.push("I".to_owned());
+        }
+        if self.delay_timer != other.delay_timer {
+            fields.push("DT".to_owned());
+        }
+        if self.sound_timer != other.sound_timer {
+            fields.push("ST".to_owned());
+        }
+        fields
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PC:{:04X} OP:{:04X} V:", self.pc, self.opcode)?;
+        for (register, value) in self.registers.iter().enumerate() {
+            if register > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{value:02X}")?;
+        }
+        write!(
+            f,
+            " I:{:04X} DT:{:02X} ST:{:02X}",
+            self.index, self.delay_timer, self.sound_timer
+        )
+    }
+}
+
+impl FromStr for TraceRecord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let mut field = |name: &str| {
+            fields
+                .next()
+                .and_then(|field| field.strip_prefix(name)?.strip_prefix(':'))
+                .ok_or_else(|| format!("expected {name}:<hex>"))
+        };
+        let hex = |value: &str| u16::from_str_radix(value, 16).map_err(|_| format!("invalid hex value '{value}'"));
+        let byte = |value: &str| u8::from_str_radix(value, 16).map_err(|_| format!("invalid hex value '{value}'"));
+
+        let pc = hex(field("PC")?)?;
+        let opcode = hex(field("OP")?)?;
+
+        let values = field("V")?.split(',').map(byte).collect::<Result<Vec<_>, _>>()?;
+        let registers = values
+            .try_into()
+            .map_err(|values: Vec<u8>| format!("expected 16 registers, got {}", values.len()))?;
+
+        let index = hex(field("I")?)?;
+        let delay_timer = byte(field("DT")?)?;
+        let sound_timer = byte(field("ST")?)?;
+
+        Ok(Self {
+            pc,
+            opcode,
+            registers,
+            index,
+            delay_timer,
+            sound_timer,
+        })
+    }
+}
+
+/// Parses a whole trace file.
+pub fn parse(text: &str) -> Result<Vec<TraceRecord>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(number, line)| line.parse().map_err(|e| format!("line {}: {e}", number + 1)))
+        .collect()
+}
+
+/// Parses a log from another interpreter with one instruction per line, made
+/// of `name=value` or `name:value` fields in any order and any case:
+///
+/// ```text
+/// pc=0x200 opcode=00E0 v0=00 v1=00 ... vf=00 i=000 dt=00 st=00 sp=0
+/// ```
+///
+/// `op` may be written `opcode`, `dt` `delay` and `st` `sound`. Values are hex,
+/// with or without `0x`, and fields not in a `TraceRecord` are ignored.
+pub fn parse_key_values(text: &str) -> Result<Vec<TraceRecord>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(number, line)| key_value_record(line).map_err(|e| format!("line {}: {e}", number + 1)))
+        .collect()
+}
+
+fn key_value_record(line: &str) -> Result<TraceRecord, String> {
+    let mut fields = HashMap::new();
+    for (name, value) in line.split_whitespace().filter_map(|field| field.split_once(['=', ':'])) {
+        let name = match name.to_ascii_lowercase().as_str() {
+            "opcode" => "op".to_owned(),
+            "delay" => "dt".to_owned(),
+            "sound" => "st".to_owned(),
+            name => name.to_owned(),
+        };
+        fields.insert(name, value);
+    }
+
+    let field = |name: &str| {
+        let value = fields.get(name).ok_or_else(|| format!("missing {name}"))?;
+        u16::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|_| format!("invalid hex value '{value}'"))
+    };
+    let byte = |name: &str| {
+        field(name).and_then(|value| u8::try_from(value).map_err(|_| format!("{name} {value:X} doesn't fit in a byte")))
+    };
+
+    let mut registers = [0; 16];
+    for (register, value) in registers.iter_mut().enumerate() {
+        *value = byte(&format!("v{register:x}"))?;
+    }
+    Ok(TraceRecord {
+        pc: field("pc")?,
+        opcode: field("op")?,
+        registers,
+        index: field("i")?,
+        delay_timer: byte("dt")?,
+        sound_timer: byte("st")?,
+    })
+}
+
+/// Runs `cpu` for up to `instructions` instructions, ticking the timers after
+/// every `instructions_per_frame` of them, and records each step.
+///
+/// There is no display wait, so a reference trace has to be made with the
+/// same fixed instructions per frame. Recording stops early on an error.
+pub fn record(cpu: &mut CPU, instructions: usize, instructions_per_frame: u32) -> (Vec<TraceRecord>, Option<CpuError>) {
+    let mut records = Vec::with_capacity(instructions);
+    for step in 0..instructions {
+        records.push(TraceRecord::capture(cpu));
+        if let Err(e) = cpu.cycle() {
+            return (records, Some(e));
+        }
+        if (step + 1) % instructions_per_frame.max(1) as usize == 0 {
+            cpu.cycle_timers();
+        }
+    }
+    (records, None)
+}
+
+/// The first place two traces disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Position of the first differing record
+    pub step: usize,
+    /// `None` when that trace ended first
+    pub expected: Option<TraceRecord>,
+    pub actual: Option<TraceRecord>,
+    /// The matching records just before the divergence
+    pub context: Vec<TraceRecord>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "traces diverge at instruction {}", self.step)?;
+        let first = self.step - self.context.len();
+        for (step, record) in (first..).zip(&self.context) {
+            writeln!(f, "  {step:>8}  {record}")?;
+        }
+
+        let line = |record: &Option<TraceRecord>| match record {
+            Some(record) => record.to_string(),
+            None => "<end of trace>".to_owned(),
+        };
+        writeln!(f, "  expected  {}", line(&self.expected))?;
+        write!(f, "  actual    {}", line(&self.actual))?;
+        if let (Some(expected), Some(actual)) = (&self.expected, &self.actual) {
+            write!(f, "\n  differs in {}", expected.differences(actual).join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares two traces record by record, including their length.
+pub fn compare(expected: &[TraceRecord], actual: &[TraceRecord]) -> Result<(), Divergence> {
+    let step = expected
+        .iter()
+        .zip(actual)
+        .position(|(expected, actual)| expected != actual)
+        .unwrap_or(expected.len().min(actual.len()));
+
+    if step == expected.len() && step == actual.len() {
+        return Ok(());
+    }
+
+    Err(Divergence {
+        step,
+        expected: expected.get(step).copied(),
+        actual: actual.get(step).copied(),
+        context: expected[step.saturating_sub(CONTEXT)..step].to_vec(),
+    })
+}
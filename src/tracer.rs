@@ -0,0 +1,418 @@
+//! Instruction tracing for debugging ROMs and the interpreter itself.
+//!
+//! Each traced instruction is one line with the frame it ran in, its address,
+//! opcode, mnemonic and everything it changed:
+//!
+//! ```text
+//!     12 20A  D125  DRW V1, V2, 5     VF 00->01
+//!     12 21E  F255  LD [I], V2        [300]=01 [301]=02 [302]=03
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use crate::{
+    cpu::{CpuError, CPU},
+    disasm::Instruction,
+};
+
+/// An inclusive range of RAM addresses, written as hex `200-2FF`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl FromStr for AddressRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid address range '{s}', expected hex START-END such as 200-2FF");
+        let (start, end) = s.split_once('-').ok_or_else(error)?;
+        let parse = |value: &str| usize::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|_| error());
+        Ok(Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+/// An inclusive range of frames, `100-200`, or `100-` for everything from frame 100 on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRange {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl FromStr for FrameRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid frame range '{s}', expected START-END or START-");
+        let (start, end) = s.split_once('-').ok_or_else(error)?;
+        Ok(Self {
+            start: start.parse().map_err(|_| error())?,
+            end: match end {
+                "" => None,
+                end => Some(end.parse().map_err(|_| error())?),
+            },
+        })
+    }
+}
+
+/// Opcode classes by their first hex digit, written as a list such as `8,D,F`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeClasses(u16);
+
+impl OpcodeClasses {
+    pub fn contains(&self, opcode: u16) -> bool {
+        self.0 & 1 << (opcode >> 12) != 0
+    }
+}
+
+impl FromStr for OpcodeClasses {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|class| match u16::from_str_radix(class.trim(), 16) {
+                Ok(class) if class < 16 => Ok(1 << class),
+                _ => Err(format!("invalid opcode class '{class}', expected a hex digit 0-F")),
+            })
+            .try_fold(0, |mask, bit| bit.map(|bit| mask | bit))
+            .map(Self)
+    }
+}
+
+/// Which instructions get traced. Everything passes when a field is `None`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceFilter {
+    pub addresses: Option<AddressRange>,
+    pub classes: Option<OpcodeClasses>,
+    pub frames: Option<FrameRange>,
+}
+
+impl TraceFilter {
+    fn matches(&self, pc: usize, opcode: u16, frame: u64) -> bool {
+        self.addresses.is_none_or(|range| (range.start..=range.end).contains(&pc))
+            && self.classes.is_none_or(|classes| classes.contains(opcode))
+            && self.frames.is_none_or(|range| frame >= range.start && range.end.is_none_or(|end| frame <= end))
+    }
+}
+
+// CPU state before an instruction ran
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    pc: usize,
+    opcode: u16,
+    registers: [u8; 16],
+    index: u16,
+    delay_timer: u8,
+    sound_timer: u8,
+}
+
+impl Snapshot {
+    fn capture(cpu: &CPU) -> Self {
+        Self {
+            pc: cpu.program_counter(),
+            opcode: cpu.peek_opcode(),
+            registers: *cpu.registers(),
+            index: cpu.index_register(),
+            delay_timer: cpu.delay_timer(),
+            sound_timer: cpu.sound_timer(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    frame: u64,
+    pc: usize,
+    opcode: u16,
+    changes: Vec<String>,
+}
+
+impl Entry {
+    fn new(frame: u64, before: &Snapshot, cpu: &CPU) -> Self {
+        let mut changes = Vec::new();
+        for (register, (old, new)) in before.registers.iter().zip(cpu.registers()).enumerate() {
+            if old != new {
+                changes.push(format!("V{register:X} {old:02X}->{new:02X}"));
+            }
+        }
+        if before.index != cpu.index_register() {
+            changes.push(format!("I {:03X}->{:03X}", before.index, cpu.index_register()));
+        }
+        if before.delay_timer != cpu.delay_timer() {
+            changes.push(format!("DT {:02X}->{:02X}", before.delay_timer, cpu.delay_timer()));
+        }
+        if before.sound_timer != cpu.sound_timer() {
+            changes.push(format!("ST {:02X}->{:02X}", before.sound_timer, cpu.sound_timer()));
+        }
+
+        // Only FX33 and FX55 write to memory, both starting at I
+        let written = match Instruction::decode(before.opcode) {
+            Instruction::LdBcd(_) => 3,
+            Instruction::Store(x) => x as usize + 1,
+            _ => 0,
+        };
+        let start = before.index as usize;
+        for (address, value) in cpu.memory().iter().enumerate().skip(start).take(written) {
+            changes.push(format!("[{address:03X}]={value:02X}"));
+        }
+
+        Self {
+            frame,
+            pc: before.pc,
+            opcode: before.opcode,
+            changes,
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mnemonic = Instruction::decode(self.opcode).to_string();
+        write!(f, "{:>6} {:03X}  {:04X}  ", self.frame, self.pc, self.opcode)?;
+        if self.changes.is_empty() {
+            write!(f, "{mnemonic}")
+        } else {
+            write!(f, "{mnemonic:<16}  {}", self.changes.join(" "))
+        }
+    }
+}
+
+/// Logs executed instructions, attached to a CPU with `CPU::set_tracer`.
+pub struct Tracer {
+    output: Box<dyn Write + Send>,
+    filter: TraceFilter,
+    // With a limit only the last instructions are kept, and written out on an error
+    last: Option<usize>,
+    buffer: VecDeque<Entry>,
+    frame: u64,
+    before: Option<Snapshot>,
+}
+
+impl Tracer {
+    /// Writes every instruction passing `filter` to `output`.
+    pub fn new(output: impl Write + Send + 'static, filter: TraceFilter) -> Self {
+        Self {
+            output: Box::new(output),
+            filter,
+            last: None,
+            buffer: VecDeque::new(),
+            frame: 0,
+            before: None,
+        }
+    }
+
+    /// Only keeps the last `count` instructions, and writes them when the CPU faults.
+    pub fn ring_buffer(mut self, count: usize) -> Self {
+        self.last = Some(count);
+        self.buffer = VecDeque::with_capacity(count);
+        self
+    }
+
+    pub(crate) fn before_instruction(&mut self, cpu: &CPU) {
+        let opcode = cpu.peek_opcode();
+        self.before = self
+            .filter
+            .matches(cpu.program_counter(), opcode, self.frame)
+            .then(|| Snapshot::capture(cpu));
+    }
+
+    pub(crate) fn after_instruction(&mut self, cpu: &CPU) {
+        let Some(before) = self.before.take() else {
+            return;
+        };
+        let entry = Entry::new(self.frame, &before, cpu);
+
+        match self.last {
+            Some(0) => {}
+            Some(count) => {
+                if self.buffer.len() == count {
+                    self.buffer.pop_front();
+                }
+                self.buffer.push_back(entry);
+            }
+            None => self.write(|output| writeln!(output, "{entry}")),
+        }
+    }
+
+    pub(crate) fn error(&mut self, error: &CpuError) {
+        self.before = None;
+        let buffer = std::mem::take(&mut self.buffer);
+        self.write(|output| {
+            if !buffer.is_empty() {
+                writeln!(output, "last {} instructions:", buffer.len())?;
+            }
+            for entry in &buffer {
+                writeln!(output, "{entry}")?;
+            }
+            writeln!(output, "error: {error}")?;
+            output.flush()
+        });
+    }
+
+    pub(crate) fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    // A failing trace file shouldn't stop the emulator, so tracing just ends
+    fn write(&mut self, write: impl FnOnce(&mut dyn Write) -> io::Result<()>) {
+        if let Err(e) = write(&mut self.output) {
+            eprintln!("trace: {e}");
+            self.output = Box::new(io::sink());
+        }
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("filter", &self.filter)
+            .field("last", &self.last)
+            .field("frame", &self.frame)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::cpu::Quirks;
+
+    // Hands the trace back to the test after the tracer wrote it
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Runs `frames` frames of `per_frame` instructions, stopping at the first fault
+    fn trace(program: &[u16], filter: TraceFilter, ring: Option<usize>, frames: usize, per_frame: usize) -> String {
+        let output = Output::default();
+        let mut tracer = Tracer::new(output.clone(), filter);
+        if let Some(count) = ring {
+            tracer = tracer.ring_buffer(count);
+        }
+
+        let mut cpu = CPU::with_quirks(Quirks::default());
+        let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        cpu.load_rom(&rom);
+        cpu.set_tracer(Some(tracer));
+        'frames: for _ in 0..frames {
+            for _ in 0..per_frame {
+                if cpu.cycle().is_err() {
+                    break 'frames;
+                }
+            }
+            cpu.cycle_timers();
+        }
+
+        let bytes = output.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn parses_filters() {
+        assert_eq!("200-2ff".parse(), Ok(AddressRange { start: 0x200, end: 0x2FF }));
+        assert_eq!("0x300-0x310".parse(), Ok(AddressRange { start: 0x300, end: 0x310 }));
+        assert!("200".parse::<AddressRange>().is_err());
+        assert_eq!("10-20".parse(), Ok(FrameRange { start: 10, end: Some(20) }));
+        assert_eq!("10-".parse(), Ok(FrameRange { start: 10, end: None }));
+        assert!("x-".parse::<FrameRange>().is_err());
+    }
+
+    #[test]
+    fn opcode_classes_are_a_set() {
+        let classes: OpcodeClasses = "8, d,F".parse().unwrap();
+        assert!(classes.contains(0x8014) && classes.contains(0xD125) && classes.contains(0xF255));
+        assert!(!classes.contains(0x6005));
+
+        // Repeats are harmless instead of adding up to another class
+        let classes: OpcodeClasses = "8,8".parse().unwrap();
+        assert!(classes.contains(0x8000) && !classes.contains(0x9000));
+        assert_eq!("F,F".parse(), Ok(OpcodeClasses(1 << 0xF)));
+        assert!("G".parse::<OpcodeClasses>().is_err());
+        assert!("10".parse::<OpcodeClasses>().is_err());
+    }
+
+    #[test]
+    fn lists_register_changes() {
+        let out = trace(&[0x6005, 0xA300, 0x6015, 0xF015, 0x1208], TraceFilter::default(), None, 1, 5);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "     0 200  6005  LD V0, 0x05       V0 00->05",
+                "     0 202  A300  LD I, 0x300       I 000->300",
+                "     0 204  6015  LD V0, 0x15       V0 05->15",
+                "     0 206  F015  LD DT, V0         DT 00->15",
+                "     0 208  1208  JP 0x208",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_memory_writes() {
+        let out = trace(&[0x60FE, 0xA300, 0xF033, 0xF155], TraceFilter::default(), None, 1, 4);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[2], "     0 204  F033  LD B, V0          [300]=02 [301]=05 [302]=04");
+        assert_eq!(lines[3], "     0 206  F155  LD [I], V1        I 300->302 [300]=FE [301]=00");
+    }
+
+    #[test]
+    fn filters_by_address_class_and_frame() {
+        let program = [0x6001, 0x7101, 0x7201, 0x1202];
+
+        let filter = TraceFilter {
+            addresses: Some(AddressRange { start: 0x202, end: 0x202 }),
+            ..TraceFilter::default()
+        };
+        let out = trace(&program, filter, None, 2, 3);
+        assert!(out.lines().all(|line| line.contains(" 202  7101")), "{out}");
+        assert_eq!(out.lines().count(), 2);
+
+        let filter = TraceFilter {
+            classes: Some("1".parse().unwrap()),
+            ..TraceFilter::default()
+        };
+        let out = trace(&program, filter, None, 2, 3);
+        assert!(out.lines().all(|line| line.contains("JP 0x202")), "{out}");
+
+        let filter = TraceFilter {
+            frames: Some(FrameRange { start: 1, end: Some(1) }),
+            ..TraceFilter::default()
+        };
+        let out = trace(&program, filter, None, 3, 3);
+        assert_eq!(out.lines().count(), 3);
+        assert!(out.lines().all(|line| line.starts_with("     1 ")), "{out}");
+    }
+
+    #[test]
+    fn ring_buffer_only_writes_on_a_fault() {
+        let out = trace(&[0x6001, 0x1200], TraceFilter::default(), Some(3), 2, 4);
+        assert_eq!(out, "");
+
+        let out = trace(&[0x6001, 0x7001, 0x7001, 0x7001, 0x00EE], TraceFilter::default(), Some(2), 1, 5);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 4, "{out}");
+        assert_eq!(lines[0], "last 2 instructions:");
+        assert!(lines[1].starts_with("     0 204  7001") && lines[2].starts_with("     0 206  7001"));
+        assert!(lines[3].starts_with("error: "), "{out}");
+    }
+}
@@ -0,0 +1,163 @@
+//! Runs test ROMs headlessly and compares the final framebuffer against a
+//! golden copy in `tests/golden`.
+//!
+//! Goldens are never written from this interpreter's output. The font goldens
+//! are checked against `chip8::font::FONT` laid out the way `tests/roms/font.ch8`
+//! draws it, see `font_goldens_match_the_font_table`. Goldens for the community
+//! suites (IBM logo, corax+, flags, quirks and keypad from Timendus'
+//! chip8-test-suite) have to be drawn from the results the suite documents,
+//! every test showing a tick, and a case without one fails.
+//!
+//! The suite's ROMs aren't vendored, so those cases are ignored locally. CI
+//! fetches them into `tests/roms` and runs them with `cargo test --test
+//! conformance -- --include-ignored`. A missing ROM fails the case instead of passing it.
+
+use std::{fs, path::PathBuf};
+
+use chip8::{
+    cpu::{QuirkPreset, Quirks, CHIP8_HEIGHT, CHIP8_WIDTH, CPU},
+    font::{FONT, SPRITE_WIDTH},
+    machine::{Machine, DEFAULT_INSTRUCTIONS_PER_FRAME},
+};
+
+// Timendus' suite skips its menu when this byte is set before starting
+const TEST_SELECT_ADDR: usize = 0x1FF;
+
+struct Case {
+    rom: &'static str,
+    preset: QuirkPreset,
+    frames: u32,
+    select: Option<u8>,
+    // Keys pressed or released, each before the given frame runs
+    keys: &'static [(u32, u8, bool)],
+}
+
+fn path(dir: &str, file: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", dir, file].iter().collect()
+}
+
+fn render(cpu: &CPU) -> String {
+    let mut out = String::with_capacity((CHIP8_WIDTH + 1) * CHIP8_HEIGHT);
+    for row in cpu.get_vram() {
+        out.extend(row.iter().map(|&pixel| if pixel != 0 { '#' } else { '.' }));
+        out.push('\n');
+    }
+    out
+}
+
+fn run(case: Case) {
+    let rom = fs::read(path("roms", case.rom))
+        .unwrap_or_else(|_| panic!("{} not found in tests/roms, see the top of tests/conformance.rs", case.rom));
+
+    let mut cpu = CPU::with_quirks(Quirks::preset(case.preset));
+    cpu.load_rom(&rom);
+    if let Some(select) = case.select {
+        cpu.memory_mut()[TEST_SELECT_ADDR] = select;
+    }
+
+    let mut machine = Machine::new(cpu, DEFAULT_INSTRUCTIONS_PER_FRAME);
+    for frame in 0..case.frames {
+        for &(_, key, pressed) in case.keys.iter().filter(|&&(at, _, _)| at == frame) {
+            machine.cpu_mut().keypress(key as usize, pressed);
+        }
+        machine.run_frame().unwrap();
+    }
+    let actual = render(machine.cpu());
+
+    // Suites with a menu get a golden per test they were started on
+    let stem = match case.select {
+        Some(select) => format!("{}-{select}", case.rom.trim_end_matches(".ch8")),
+        None => case.rom.trim_end_matches(".ch8").to_owned(),
+    };
+    let golden = path("golden", &format!("{stem}.{:?}.txt", case.preset).to_lowercase());
+    let expected = fs::read_to_string(&golden).unwrap_or_else(|_| {
+        panic!("missing {}, see the top of tests/conformance.rs", golden.display())
+    });
+    assert!(
+        actual == expected,
+        "{} ({:?}) differs from {}\nexpected:\n{expected}\nactual:\n{actual}",
+        case.rom,
+        case.preset,
+        golden.display()
+    );
+}
+
+macro_rules! conformance {
+    ($($(#[$meta:meta])* $name:ident: $rom:literal, $preset:ident, $frames:literal, $select:expr, $keys:expr;)*) => {
+        $(
+            #[test]
+            $(#[$meta])*
+            fn $name() {
+                run(Case {
+                    rom: $rom,
+                    preset: QuirkPreset::$preset,
+                    frames: $frames,
+                    select: $select,
+                    keys: $keys,
+                });
+            }
+        )*
+    };
+}
+
+// Holds key 5 down from frame 30 on
+const HOLD: &[(u32, u8, bool)] = &[(30, 0x5, true)];
+// Taps key A, FX0A only returns once it is released
+const TAP: &[(u32, u8, bool)] = &[(30, 0xA, true), (40, 0xA, false)];
+
+conformance! {
+    font_chip8: "font.ch8", Chip8, 60, None, &[];
+    font_schip: "font.ch8", Schip, 60, None, &[];
+    #[ignore = "needs ROM"]
+    ibm_logo_chip8: "2-ibm-logo.ch8", Chip8, 60, None, &[];
+    #[ignore = "needs ROM"]
+    ibm_logo_schip: "2-ibm-logo.ch8", Schip, 60, None, &[];
+    #[ignore = "needs ROM"]
+    corax_chip8: "3-corax+.ch8", Chip8, 120, None, &[];
+    #[ignore = "needs ROM"]
+    corax_schip: "3-corax+.ch8", Schip, 120, None, &[];
+    #[ignore = "needs ROM"]
+    flags_chip8: "4-flags.ch8", Chip8, 240, None, &[];
+    #[ignore = "needs ROM"]
+    flags_schip: "4-flags.ch8", Schip, 240, None, &[];
+    #[ignore = "needs ROM"]
+    quirks_chip8: "5-quirks.ch8", Chip8, 600, Some(1), &[];
+    #[ignore = "needs ROM"]
+    quirks_schip: "5-quirks.ch8", Schip, 600, Some(2), &[];
+    #[ignore = "needs ROM"]
+    keypad_ex9e_down_chip8: "6-keypad.ch8", Chip8, 60, Some(1), HOLD;
+    #[ignore = "needs ROM"]
+    keypad_ex9e_up_chip8: "6-keypad.ch8", Chip8, 60, Some(2), HOLD;
+    #[ignore = "needs ROM"]
+    keypad_fx0a_chip8: "6-keypad.ch8", Chip8, 60, Some(3), TAP;
+    #[ignore = "needs ROM"]
+    keypad_fx0a_schip: "6-keypad.ch8", Schip, 60, Some(3), TAP;
+}
+
+// font.ch8 draws the glyphs 0-F eight to a row, 8 pixels apart and 6 rows apart
+fn font_sheet() -> String {
+    let mut screen = [[false; CHIP8_WIDTH]; CHIP8_HEIGHT];
+    for (glyph, sprite) in FONT.chunks_exact(SPRITE_WIDTH as usize).enumerate() {
+        let (x, y) = (8 * (glyph % 8), 6 * (glyph / 8));
+        for (row, &bits) in sprite.iter().enumerate() {
+            for column in 0..8 {
+                screen[y + row][x + column] = bits & (0x80 >> column) != 0;
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for row in screen {
+        out.extend(row.iter().map(|&lit| if lit { '#' } else { '.' }));
+        out.push('\n');
+    }
+    out
+}
+
+#[test]
+fn font_goldens_match_the_font_table() {
+    for preset in ["chip8", "schip"] {
+        let golden = fs::read_to_string(path("golden", &format!("font.{preset}.txt"))).unwrap();
+        assert_eq!(golden, font_sheet(), "font.{preset}.txt");
+    }
+}
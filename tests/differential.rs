@@ -0,0 +1,151 @@
+//! Runs ROMs through the CPU and compares each instruction against reference
+//! traces in `tests/traces`, named `<rom>.<preset>.trace` after a ROM in
+//! `tests/roms`. Logs from another interpreter can be dropped in as
+//! `<rom>.<preset>.log` instead, read with `chip8::trace::parse_key_values`.
+//!
+//! Reference traces are recorded at `DEFAULT_INSTRUCTIONS_PER_FRAME` with the
+//! timers ticking after each frame's worth of instructions, see
+//! `chip8::trace::record`. A trace whose ROM is missing fails the test.
+//!
+//! `font.chip8.trace` was checked by hand against the program listing, it
+//! doesn't come from another interpreter. No log from another interpreter is
+//! committed yet, so `has_a_log_from_another_interpreter` stays ignored until
+//! one is added next to its ROM.
+
+use std::{fs, path::PathBuf};
+
+use chip8::{
+    cpu::{QuirkPreset, Quirks, CPU},
+    machine::DEFAULT_INSTRUCTIONS_PER_FRAME,
+    trace::{self, TraceRecord},
+};
+
+type Parser = fn(&str) -> Result<Vec<TraceRecord>, String>;
+
+fn dir(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", name].iter().collect()
+}
+
+fn run(rom: &[u8], preset: QuirkPreset, instructions: usize) -> Vec<TraceRecord> {
+    let mut cpu = CPU::with_quirks(Quirks::preset(preset));
+    cpu.load_rom(rom);
+    let (records, error) = trace::record(&mut cpu, instructions, DEFAULT_INSTRUCTIONS_PER_FRAME);
+    if let Some(e) = error {
+        eprintln!("stopped after {} instructions: {e}", records.len());
+    }
+    records
+}
+
+fn font_trace() -> Vec<TraceRecord> {
+    let text = fs::read_to_string(dir("traces").join("font.chip8.trace")).unwrap();
+    trace::parse(&text).unwrap()
+}
+
+#[test]
+fn reference_traces_match() {
+    let mut failures = Vec::new();
+
+    for entry in fs::read_dir(dir("traces")).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let (stem, parse): (_, Parser) = match (name.strip_suffix(".trace"), name.strip_suffix(".log")) {
+            (Some(stem), _) => (stem, trace::parse),
+            (_, Some(stem)) => (stem, trace::parse_key_values),
+            _ => continue,
+        };
+        let (rom, preset) = stem.rsplit_once('.').unwrap_or_else(|| panic!("{name}: expected <rom>.<preset>"));
+        let preset: QuirkPreset = preset.parse().unwrap();
+
+        let Ok(bytes) = fs::read(dir("roms").join(format!("{rom}.ch8"))) else {
+            failures.push(format!("{name}: {rom}.ch8 not found in tests/roms"));
+            continue;
+        };
+
+        let expected = parse(&fs::read_to_string(&path).unwrap()).unwrap_or_else(|e| panic!("{name}: {e}"));
+        let actual = run(&bytes, preset, expected.len());
+        if let Err(divergence) = trace::compare(&expected, &actual) {
+            failures.push(format!("{name}: {divergence}"));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
+
+#[test]
+#[ignore = "needs a log recorded by another interpreter"]
+fn has_a_log_from_another_interpreter() {
+    let logs: Vec<_> = fs::read_dir(dir("traces"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect();
+    // reference_traces_match compares each of them
+    assert!(!logs.is_empty(), "no <rom>.<preset>.log in tests/traces");
+}
+
+#[test]
+fn records_round_trip_through_text() {
+    let records = font_trace();
+    let text: String = records.iter().map(|record| format!("{record}\n")).collect();
+    assert_eq!(trace::parse(&text).unwrap(), records);
+}
+
+#[test]
+fn divergence_reports_first_differing_record() {
+    let expected = font_trace();
+    let mut actual = expected.clone();
+    actual[20].registers[3] ^= 1;
+    actual[20].index += 1;
+    actual[30].pc += 2;
+
+    let divergence = trace::compare(&expected, &actual).unwrap_err();
+    assert_eq!(divergence.step, 20);
+    assert_eq!(divergence.context, expected[15..20]);
+    assert_eq!(expected[20].differences(&actual[20]), ["V3", "I"]);
+
+    let report = divergence.to_string();
+    assert!(report.contains("instruction 20"), "{report}");
+    assert!(report.contains("differs in V3, I"), "{report}");
+}
+
+#[test]
+fn divergence_reports_trace_ending_early() {
+    let expected = font_trace();
+    let divergence = trace::compare(&expected, &expected[..10]).unwrap_err();
+    assert_eq!(divergence.step, 10);
+    assert_eq!(divergence.actual, None);
+    assert!(divergence.to_string().contains("<end of trace>"));
+}
+
+#[test]
+fn converts_key_value_logs() {
+    let expected = font_trace();
+    let log: String = expected[..20]
+        .iter()
+        .map(|record| {
+            let registers: Vec<String> =
+                record.registers.iter().enumerate().map(|(n, value)| format!("v{n:x}={value:02x}")).collect();
+            format!(
+                "cycle=9 pc=0x{:03x} sp=0 opcode={:04x} {} I={:03X} delay={:x} sound={:x}\n",
+                record.pc,
+                record.opcode,
+                registers.join(" "),
+                record.index,
+                record.delay_timer,
+                record.sound_timer
+            )
+        })
+        .collect();
+    assert_eq!(trace::parse_key_values(&log).unwrap(), expected[..20]);
+
+    let error = trace::parse_key_values("# header\npc=200 op=00e0 v0=00 i=0 dt=0 st=0\n").unwrap_err();
+    assert_eq!(error, "line 2: missing v1");
+    let error = trace::parse_key_values("pc=200 op=00e0 v0=100").unwrap_err();
+    assert_eq!(error, "line 1: v0 100 doesn't fit in a byte");
+}
+
+#[test]
+fn malformed_records_report_their_line() {
+    let error = trace::parse("# comment\n\nPC:0200 OP:00E0 V:00 I:0000 DT:00 ST:00\n").unwrap_err();
+    assert_eq!(error, "line 3: expected 16 registers, got 1");
+}
@@ -0,0 +1,179 @@
+//! Drives the GDB stub over a loopback socket the way GDB would.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use chip8::{
+    cpu::{QuirkPreset, Quirks, CPU},
+    gdb::GdbStub,
+};
+
+// 200: V0 = 5, 202: V1 = 7, 204: V0 += V1, 206: call 20A, 208: jump 208, 20A: return
+const ROM: [u8; 12] = [0x60, 0x05, 0x61, 0x07, 0x80, 0x14, 0x22, 0x0A, 0x12, 0x08, 0x00, 0xEE];
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn send(&mut self, packet: &str) {
+        let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.writer, "${packet}#{checksum:02x}").unwrap();
+    }
+
+    // Next packet from the stub, skipping acks and checking its checksum
+    fn receive(&mut self) -> String {
+        let mut byte = [0];
+        while byte[0] != b'$' {
+            self.reader.read_exact(&mut byte).unwrap();
+        }
+        let mut packet = Vec::new();
+        self.reader.read_until(b'#', &mut packet).unwrap();
+        packet.pop();
+        let mut checksum = [0; 2];
+        self.reader.read_exact(&mut checksum).unwrap();
+
+        let expected = packet.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap();
+        assert_eq!(checksum, expected);
+        String::from_utf8(packet).unwrap()
+    }
+
+    fn request(&mut self, packet: &str) -> String {
+        self.send(packet);
+        self.receive()
+    }
+}
+
+fn session(test: impl FnOnce(&mut Client)) -> CPU {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let mut cpu = CPU::with_quirks(Quirks::preset(QuirkPreset::Chip8));
+        cpu.load_rom(&ROM);
+        let mut stub = GdbStub::new(cpu);
+        let (stream, _) = listener.accept().unwrap();
+        stub.serve(stream).unwrap();
+        stub.into_cpu()
+    });
+
+    let stream = TcpStream::connect(address).unwrap();
+    let mut client = Client {
+        reader: BufReader::new(stream.try_clone().unwrap()),
+        writer: stream,
+    };
+    test(&mut client);
+    client.send("k");
+    server.join().unwrap()
+}
+
+#[test]
+fn reports_features_and_stop_reason() {
+    session(|client| {
+        assert!(client.request("qSupported:multiprocess+").contains("qXfer:features:read+"));
+        assert_eq!(client.request("?"), "S05");
+        assert!(client.request("qXfer:features:read:target.xml:0,fff").starts_with("l<?xml"));
+        assert_eq!(client.request("vMustReplyEmpty"), "");
+    });
+}
+
+#[test]
+fn reads_and_writes_registers() {
+    let cpu = session(|client| {
+        // V0-VF, I, PC (little endian), SP, DT, ST
+        let expected = format!("{}{}{}{}", "00".repeat(16), "0000", "0002", "000000");
+        assert_eq!(client.request("g"), expected);
+
+        assert_eq!(client.request("P3=2a"), "OK");
+        assert_eq!(client.request("P10=3412"), "OK");
+        assert_eq!(client.request("p3"), "2a");
+        assert_eq!(client.request("p10"), "3412");
+        // The stack pointer is read only
+        assert_eq!(client.request("P12=05"), "E01");
+    });
+    assert_eq!(cpu.registers()[3], 0x2A);
+    assert_eq!(cpu.index_register(), 0x1234);
+}
+
+#[test]
+fn reads_and_writes_memory() {
+    let cpu = session(|client| {
+        assert_eq!(client.request("m200,4"), "60056107");
+        assert_eq!(client.request("M300,3:abcdef"), "OK");
+        assert_eq!(client.request("m300,3"), "abcdef");
+        assert_eq!(client.request("mfff,2"), "E01");
+        assert_eq!(client.request("mffffffffffffffff,1"), "E01");
+        assert_eq!(client.request("M1,ffffffffffffffff:00"), "E01");
+    });
+    assert_eq!(cpu.memory()[0x300..0x303], [0xAB, 0xCD, 0xEF]);
+}
+
+#[test]
+fn asks_for_packets_with_bad_checksums_again() {
+    session(|client| {
+        let mut ack = [0];
+        write!(client.writer, "$m200,2#00").unwrap();
+        client.reader.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'-');
+
+        client.send("m200,2");
+        client.reader.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+');
+        assert_eq!(client.receive(), "6005");
+    });
+}
+
+#[test]
+fn steps_and_stops_at_breakpoints() {
+    session(|client| {
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p11"), "0202");
+        assert_eq!(client.request("p0"), "05");
+
+        assert_eq!(client.request("Z0,20a,2"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p11"), "0a02");
+        assert_eq!(client.request("p0"), "0c");
+        // Inside the subroutine
+        assert_eq!(client.request("p12"), "01");
+
+        assert_eq!(client.request("z0,20a,2"), "OK");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p11"), "0802");
+        assert_eq!(client.request("p12"), "00");
+    });
+}
+
+#[test]
+fn interrupts_a_running_target() {
+    session(|client| {
+        client.send("c");
+        client.writer.write_all(&[0x03]).unwrap();
+        assert_eq!(client.receive(), "S02");
+        // Still spinning on the jump at 208
+        assert_eq!(client.request("p11"), "0802");
+    });
+}
+
+#[test]
+fn reports_faults() {
+    session(|client| {
+        // Return with an empty stack
+        assert_eq!(client.request("P11=0a02"), "OK");
+        let console = client.request("c");
+        assert!(console.starts_with('O'), "{console}");
+        assert_eq!(client.receive(), "S0b");
+
+        // Not an instruction
+        assert_eq!(client.request("M300,2:ffff"), "OK");
+        assert_eq!(client.request("P11=0003"), "OK");
+        let console = client.request("s");
+        assert!(console.starts_with('O'), "{console}");
+        assert_eq!(client.receive(), "S04");
+    });
+}